@@ -1,11 +1,42 @@
-use std::ops::{Index, IndexMut};
-
 use crate::util::Component;
+use crate::util::PortInfo;
+use crate::util::PortKind;
+
+const INPUTS: [PortInfo; 2] = [
+    PortInfo {
+        name: "amp_cv",
+        kind: PortKind::Cv,
+        unit: "level",
+        min: i16::MIN,
+        max: i16::MAX,
+        default: i16::MAX,
+        description: "The gain, as a fraction of full scale; negative values invert.",
+    },
+    PortInfo {
+        name: "in_cv",
+        kind: PortKind::Audio,
+        unit: "",
+        min: i16::MIN,
+        max: i16::MAX,
+        default: 0,
+        description: "The signal to amplify.",
+    },
+];
+
+const OUTPUTS: [PortInfo; 1] = [PortInfo {
+    name: "out",
+    kind: PortKind::Audio,
+    unit: "",
+    min: i16::MIN,
+    max: i16::MAX,
+    default: 0,
+    description: "in_cv scaled by amp_cv.",
+}];
+
 pub struct Vca {
     pub in_cv: i16,
     pub amp_cv: i16,
     pub out_cv: i16,
-    pub dummy: i16,
 }
 
 impl Vca {
@@ -14,44 +45,51 @@ impl Vca {
             in_cv: 0,
             amp_cv: init_amp_cv,
             out_cv: 0,
-            dummy: 0,
         }
     }
 }
 
 impl Component for Vca {
-    fn tick(&mut self) {}
+    fn tick(&mut self, _velocity: i16) {}
     fn step(&mut self) {
         // Does left shift work the way I want with signed values?
         // I am trying to use the amp_cv as essentially as a signed Q1.15
         self.out_cv = (((self.amp_cv as i32) * (self.in_cv as i32)) >> 15) as i16;
     }
-    fn inputs(&self) -> Vec<&'static str> {
-        vec!["amp_in", "in_cv"]
+    fn process(&mut self, len: usize, inputs: &[Option<&[i16]>], outputs: &mut [&mut [i16]]) {
+        if len == 0 {
+            return;
+        }
+        let out = &mut outputs[0][..len];
+        for (n, o) in out.iter_mut().enumerate() {
+            let amp_cv = inputs[0].map_or(self.amp_cv, |x| x[n]);
+            let in_cv = inputs[1].map_or(self.in_cv, |x| x[n]);
+            *o = (((amp_cv as i32) * (in_cv as i32)) >> 15) as i16;
+        }
+        self.amp_cv = inputs[0].map_or(self.amp_cv, |x| x[len - 1]);
+        self.in_cv = inputs[1].map_or(self.in_cv, |x| x[len - 1]);
+        self.out_cv = out[len - 1];
+    }
+    fn input_info(&self) -> &'static [PortInfo] {
+        &INPUTS
     }
 
-    fn outputs(&self) -> Vec<&'static str> {
-        vec!["out"]
+    fn output_info(&self) -> &'static [PortInfo] {
+        &OUTPUTS
     }
-}
 
-impl Index<&str> for Vca {
-    type Output = i16;
-
-    fn index(&self, i: &str) -> &Self::Output {
-        match i {
-            "out" => &self.out_cv,
-            _ => &0,
+    fn input_mut(&mut self, slot: usize) -> &mut i16 {
+        match slot {
+            0 => &mut self.amp_cv,
+            1 => &mut self.in_cv,
+            _ => panic!("Vca has no input slot {}", slot),
         }
     }
-}
 
-impl IndexMut<&str> for Vca {
-    fn index_mut(&mut self, i: &str) -> &mut Self::Output {
-        match i {
-            "amp_cv" => &mut self.amp_cv,
-            "in_cv" => &mut self.in_cv,
-            _ => &mut self.dummy,
+    fn output(&self, slot: usize) -> i16 {
+        match slot {
+            0 => self.out_cv,
+            _ => panic!("Vca has no output slot {}", slot),
         }
     }
 }
@@ -1,9 +1,11 @@
 use std::fmt;
 use std::ops::{Add, Sub};
-use std::ops::{Index, IndexMut};
 
 use crate::util::Component;
+use crate::util::PortInfo;
+use crate::util::PortKind;
 use std::convert::From;
+use std::str::FromStr;
 
 // Twelve-tone equal temper notes, a.k.a. piano tuning.
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -103,6 +105,34 @@ impl fmt::Display for TtetNote {
     }
 }
 
+// Parses a note name, e.g. "C", "Fs" or "F#", "Bb".
+impl FromStr for TtetNote {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "A" => Ok(TtetNote::A),
+            "As" | "A#" => Ok(TtetNote::As),
+            "Bb" => Ok(TtetNote::Bb),
+            "B" => Ok(TtetNote::B),
+            "C" => Ok(TtetNote::C),
+            "Cs" | "C#" => Ok(TtetNote::Cs),
+            "Db" => Ok(TtetNote::Db),
+            "D" => Ok(TtetNote::D),
+            "Ds" | "D#" => Ok(TtetNote::Ds),
+            "Eb" => Ok(TtetNote::Eb),
+            "E" => Ok(TtetNote::E),
+            "F" => Ok(TtetNote::F),
+            "Fs" | "F#" => Ok(TtetNote::Fs),
+            "Gb" => Ok(TtetNote::Gb),
+            "G" => Ok(TtetNote::G),
+            "Gs" | "G#" => Ok(TtetNote::Gs),
+            "Ab" => Ok(TtetNote::Ab),
+            _ => Err(format!("unknown note `{}`", s)),
+        }
+    }
+}
+
 impl Add<i16> for TtetNote {
     type Output = Self;
 
@@ -151,14 +181,46 @@ impl TtetNote {
     }
 }
 
+const INPUTS: [PortInfo; 2] = [
+    PortInfo {
+        name: "trigger_in",
+        kind: PortKind::Trigger,
+        unit: "",
+        min: i16::MIN,
+        max: i16::MAX,
+        default: 0,
+        description:
+            "Moves on to the next note of the scale each time it goes from zero to non-zero.",
+    },
+    PortInfo {
+        name: "gate_in",
+        kind: PortKind::Gate,
+        unit: "",
+        min: i16::MIN,
+        max: i16::MAX,
+        default: 0,
+        description: "Not used yet.",
+    },
+];
+
+const OUTPUTS: [PortInfo; 1] = [PortInfo {
+    name: "note_cv_out",
+    kind: PortKind::Pitch,
+    unit: "note",
+    min: 0,
+    max: 127,
+    default: 0,
+    description: "The current note, for a WaveTableOsc's freq.",
+}];
+
 pub struct BasicArp {
     gate_in: i16,
     trigger_in: i16,
+    prev_trigger_in: i16,
     counter: usize,
     pub notes: [TtetNote; 7],
     note_cv_out: i16,
     pub octave: u16,
-    dummy: i16,
 }
 
 // A basic arpeggiator that simply cycles thought 7 notes, one per trigger.
@@ -167,51 +229,46 @@ impl BasicArp {
         BasicArp {
             gate_in: 0,
             trigger_in: 0,
+            prev_trigger_in: 0,
             counter: 0,
             notes: [TtetNote::Eb; 7],
             note_cv_out: 0,
             octave: 4,
-            dummy: 0,
         }
     }
 }
 
 impl Component for BasicArp {
-    fn tick(&mut self) {}
+    fn tick(&mut self, _velocity: i16) {}
     fn step(&mut self) {
-        // NB I'm not sure why this isn't working in the trigger, but
-        // I can track that down later.
-        if self.trigger_in != 0 {
+        // On the edge only, so that a gate held high, as from a Clock,
+        // moves it on once.
+        if self.trigger_in != 0 && self.prev_trigger_in == 0 {
             self.counter = (self.counter + 1) % self.notes.len();
             self.note_cv_out = self.notes[self.counter as usize].to_freq_cv(self.octave);
         }
+        self.prev_trigger_in = self.trigger_in;
     }
-    fn inputs(&self) -> Vec<&'static str> {
-        vec!["trigger_in", "gate_in"]
+    fn input_info(&self) -> &'static [PortInfo] {
+        &INPUTS
     }
 
-    fn outputs(&self) -> Vec<&'static str> {
-        vec!["note_cv_out"]
+    fn output_info(&self) -> &'static [PortInfo] {
+        &OUTPUTS
     }
-}
 
-impl Index<&str> for BasicArp {
-    type Output = i16;
-
-    fn index(&self, i: &str) -> &Self::Output {
-        match i {
-            "note_cv_out" => &self.note_cv_out,
-            _ => &0,
+    fn input_mut(&mut self, slot: usize) -> &mut i16 {
+        match slot {
+            0 => &mut self.trigger_in,
+            1 => &mut self.gate_in,
+            _ => panic!("BasicArp has no input slot {}", slot),
         }
     }
-}
 
-impl IndexMut<&str> for BasicArp {
-    fn index_mut(&mut self, i: &str) -> &mut Self::Output {
-        match i {
-            "gate_in" => &mut self.gate_in,
-            "trigger_in" => &mut self.trigger_in,
-            _ => &mut self.dummy,
+    fn output(&self, slot: usize) -> i16 {
+        match slot {
+            0 => self.note_cv_out,
+            _ => panic!("BasicArp has no output slot {}", slot),
         }
     }
 }
@@ -1,366 +1,398 @@
-use std::ops::{Index, IndexMut};
+use std::mem;
 use std::sync::atomic::AtomicI16;
 use std::sync::atomic::Ordering;
+use std::sync::mpsc::channel;
 use std::sync::mpsc::Receiver;
+use std::sync::mpsc::RecvTimeoutError;
 use std::sync::mpsc::Sender;
-use std::sync::mpsc::TryRecvError;
 use std::sync::Arc;
-use std::sync::RwLock;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
 
+use rtrb::Producer;
+use rtrb::PushError;
+use rtrb::RingBuffer;
+
+use crate::engine::Engine;
+use crate::groove::Groove;
+use crate::patcher::Patcher;
 use crate::ui::Cmd;
-use crate::util;
 
 use crate::amp;
 use crate::env;
+use crate::input;
 use crate::mix;
 use crate::osc;
 use crate::out;
+use crate::poly;
 use crate::rvb;
 use crate::seq;
-use crate::util::RATE;
 
 use crate::util::Component;
 
 use crate::arp;
 
-enum AvailableComponents {
+pub enum AvailableComponents {
     Adsr(env::Adsr),
+    AudioIn(input::AudioIn),
     BasicArp(arp::BasicArp),
     BasicSeq(seq::BasicSeq),
+    Clock(seq::Clock),
     Mixer(mix::Mixer),
+    Pan(mix::Pan),
     Vca(amp::Vca),
-    WaveTableOsc(osc::WaveTableOsc),
+    // Boxed, since its tables make it far bigger than the rest.
+    WaveTableOsc(Box<osc::WaveTableOsc>),
     BasicReverb(rvb::BasicReverb),
+    Voices(poly::Voices),
 }
 
 impl AvailableComponents {
-    fn step(&mut self) {
+    pub fn as_component(&self) -> &dyn Component {
         match self {
-            AvailableComponents::Adsr(x) => x.step(),
-            AvailableComponents::BasicArp(x) => x.step(),
-            AvailableComponents::BasicSeq(x) => x.step(),
-            AvailableComponents::Mixer(x) => x.step(),
-            AvailableComponents::Vca(x) => x.step(),
-            AvailableComponents::WaveTableOsc(x) => x.step(),
-            AvailableComponents::BasicReverb(x) => x.step(),
+            AvailableComponents::Adsr(x) => x,
+            AvailableComponents::AudioIn(x) => x,
+            AvailableComponents::BasicArp(x) => x,
+            AvailableComponents::BasicSeq(x) => x,
+            AvailableComponents::Clock(x) => x,
+            AvailableComponents::Mixer(x) => x,
+            AvailableComponents::Pan(x) => x,
+            AvailableComponents::Vca(x) => x,
+            AvailableComponents::WaveTableOsc(x) => x.as_ref(),
+            AvailableComponents::BasicReverb(x) => x,
+            AvailableComponents::Voices(x) => x,
         }
     }
-    fn tick(&mut self) {
+    pub fn as_component_mut(&mut self) -> &mut dyn Component {
         match self {
-            AvailableComponents::Adsr(x) => x.tick(),
-            AvailableComponents::BasicArp(x) => x.tick(),
-            AvailableComponents::BasicSeq(x) => x.tick(),
-            AvailableComponents::Mixer(x) => x.tick(),
-            AvailableComponents::Vca(x) => x.tick(),
-            AvailableComponents::WaveTableOsc(x) => x.tick(),
-            AvailableComponents::BasicReverb(x) => x.tick(),
+            AvailableComponents::Adsr(x) => x,
+            AvailableComponents::AudioIn(x) => x,
+            AvailableComponents::BasicArp(x) => x,
+            AvailableComponents::BasicSeq(x) => x,
+            AvailableComponents::Clock(x) => x,
+            AvailableComponents::Mixer(x) => x,
+            AvailableComponents::Pan(x) => x,
+            AvailableComponents::Vca(x) => x,
+            AvailableComponents::WaveTableOsc(x) => x.as_mut(),
+            AvailableComponents::BasicReverb(x) => x,
+            AvailableComponents::Voices(x) => x,
         }
     }
 }
-impl Index<&str> for AvailableComponents {
-    type Output = i16;
 
-    fn index(&self, i: &str) -> &Self::Output {
-        match self {
-            AvailableComponents::Adsr(x) => x.index(i),
-            AvailableComponents::BasicArp(x) => x.index(i),
-            AvailableComponents::BasicSeq(x) => x.index(i),
-            AvailableComponents::Mixer(x) => x.index(i),
-            AvailableComponents::Vca(x) => x.index(i),
-            AvailableComponents::WaveTableOsc(x) => x.index(i),
-            AvailableComponents::BasicReverb(x) => x.index(i),
-        }
-    }
-}
-
-impl IndexMut<&str> for AvailableComponents {
-    fn index_mut(&mut self, i: &str) -> &mut Self::Output {
-        match self {
-            AvailableComponents::Adsr(x) => x.index_mut(i),
-            AvailableComponents::BasicArp(x) => x.index_mut(i),
-            AvailableComponents::BasicSeq(x) => x.index_mut(i),
-            AvailableComponents::Mixer(x) => x.index_mut(i),
-            AvailableComponents::Vca(x) => x.index_mut(i),
-            AvailableComponents::WaveTableOsc(x) => x.index_mut(i),
-            AvailableComponents::BasicReverb(x) => x.index_mut(i),
-        }
-    }
-}
+// The most commands that can be waiting for the audio thread, or for the
+// control thread to pick up from it.
+const MAX_CMDS: usize = 1024;
+// How often the time spent in each component is sent to the UI.
+const CPU_EVERY: Duration = Duration::from_millis(500);
+// How often the levels of the master and the mixers are sent to the UI.
+const METERS_EVERY: Duration = Duration::from_millis(100);
 
+// Starts playing the patch. Commands from the UI go to a control thread,
+// which repatches, changes patterns and loads wavetables itself, and passes
+// the rest on to the audio thread through a wait-free queue. The audio
+// thread picks them up between blocks and sends each one back, with its
+// result, through another queue, so that it neither allocates nor frees
+// anything, and the control thread passes the results on to the UI.
 pub fn spawn_audio(
+    mut engine: Engine,
+    mut out: out::CpalOut,
     rx: Receiver<Cmd>,
     tx2: Sender<Cmd>,
     setbeat: Arc<AtomicI16>,
-) -> Option<out::CpalOut> {
-    let mut cv_note_map = [0f64; 256];
-    let mut ipc_64_map = [0u32; 256];
-    let mut ipc_64_e_map = [0i32; 256];
-
-    for cv in 0..128 {
-        let (f, ipc_64, e) = util::cv_to_64th_wavetable_increment(cv as i16);
-        cv_note_map[cv] = f;
-        ipc_64_map[cv] = ipc_64;
-        ipc_64_e_map[cv] = (10_000. * e) as i32;
-
-        //println!("cv={} f={:5.2} ipc={} err={}", cv, f, ipc_64, e);
+) -> anyhow::Result<out::CpalOut> {
+    for ((component, port), value) in engine.params() {
+        tx2.send(Cmd::ParamChanged {
+            component,
+            port,
+            value,
+        })
+        .unwrap();
     }
-
-    let mut wto1 = osc::WaveTableOsc::sin(ipc_64_map, 69);
-    wto1.modulation_idx = i16::max_value();
-
-    let wto1o = osc::WaveTableOsc::square(ipc_64_map, 69);
-    wto1.modulation_idx = i16::max_value();
-
-    let mut wto2 = osc::WaveTableOsc::sin(ipc_64_map, 0);
-    wto2.modulation_idx = i16::max_value();
-
-    let vca1 = amp::Vca::new(i16::max_value());
-
-    let mut adsr1 = env::Adsr::new();
-    adsr1["attack_for"] = 2048;
-    adsr1["attack_to"] = i16::max_value() / 10;
-    adsr1["decay_for"] = 1024;
-    adsr1["sustain_at"] = i16::max_value() / 20;
-    adsr1["release_for"] = 4096;
-
-    tx2.send(Cmd::AdsrAttackFor(adsr1["attack_for"])).unwrap();
-    tx2.send(Cmd::AdsrAttackTo(adsr1["attack_to"])).unwrap();
-    tx2.send(Cmd::AdsrDecayFor(adsr1["decay_for"])).unwrap();
-    tx2.send(Cmd::AdsrSustainAt(adsr1["sustain_at"])).unwrap();
-    tx2.send(Cmd::AdsrReleaseFor(adsr1["release_for"])).unwrap();
-    println!("in audio release_for {}", adsr1["release_for"]);
-
-    let mut beats = [false; 16];
-    beats[0] = true;
-    beats[4] = true;
-    beats[12] = true;
-    for (i, b) in beats.iter().enumerate() {
-        tx2.send(Cmd::Beat(i as i16, *b)).unwrap()
+    if let Some(AvailableComponents::BasicSeq(seq1)) = engine.component("seq1") {
+        for (i, b) in seq1.pattern.beats().iter().enumerate() {
+            tx2.send(Cmd::Beat(i as i16, *b)).unwrap()
+        }
     }
-    let beats = Arc::new(RwLock::new(beats));
-    let beat_len = Arc::new(RwLock::new([128; 16]));
-    let seq1 = seq::BasicSeq::new(Arc::clone(&beats), Arc::clone(&beat_len));
-
-    let vca1o = amp::Vca::new(i16::max_value());
-
-    let mut adsr1o = env::Adsr::new();
-    adsr1o["attack_for"] = 2048;
-    adsr1o["attack_to"] = i16::max_value() / 4 * 3;
-    adsr1o["decay_for"] = 1024;
-    adsr1o["sustain_at"] = i16::max_value() / 2;
-    adsr1o["release_for"] = 4096 * 4;
-
-    let obeats = [false; 16];
-    //obeats[2] = true;
-    //obeats[6] = true;
-    //obeats[10] = true;
-    //obeats[14] = true;
-    for (i, b) in obeats.iter().enumerate() {
-        tx2.send(Cmd::Obeat(i as i16, *b)).unwrap()
+    if let Some(AvailableComponents::BasicSeq(seq1o)) = engine.component("seq1o") {
+        for (i, b) in seq1o.pattern.beats().iter().enumerate() {
+            tx2.send(Cmd::Obeat(i as i16, *b)).unwrap()
+        }
+    }
+    if let Some(AvailableComponents::BasicArp(arp1)) = engine.component("arp1") {
+        tx2.send(Cmd::Scale(arp1.notes[0])).unwrap();
     }
-    let obeats = Arc::new(RwLock::new(obeats));
-    let obeat_len = Arc::new(RwLock::new([64; 16]));
-    let seq1o = seq::BasicSeq::new(Arc::clone(&obeats), Arc::clone(&obeat_len));
-
-    let mut mix1 = mix::Mixer::new();
-    mix1["a_lvl"] = i16::max_value();
-    mix1["b_lvl"] = i16::max_value();
-
-    let mut arp1 = arp::BasicArp::new();
-    arp1.notes = arp::TtetNote::C.major_scale();
-    arp1.octave = 3;
-    tx2.send(Cmd::Scale(arp::TtetNote::C)).unwrap();
-
-    let mut arp1o = arp::BasicArp::new();
-    arp1o.notes = arp::TtetNote::Fs.major_scale();
-
-    let mut rvb1 = rvb::BasicReverb::new();
-    rvb1.delay[0] = (RATE / 3) as i16;
-    rvb1.delay[1] = (RATE / 5) as i16;
-    rvb1.delay[2] = (RATE / 8) as i16;
-
-    let mut components: Vec<(&str, AvailableComponents)> = vec![
-        ("wto1", AvailableComponents::WaveTableOsc(wto1)),
-        ("wto1o", AvailableComponents::WaveTableOsc(wto1o)),
-        ("wto2", AvailableComponents::WaveTableOsc(wto2)),
-        ("vca1", AvailableComponents::Vca(vca1)),
-        ("adsr1", AvailableComponents::Adsr(adsr1)),
-        ("seq1", AvailableComponents::BasicSeq(seq1)),
-        ("vca1o", AvailableComponents::Vca(vca1o)),
-        ("adsr1o", AvailableComponents::Adsr(adsr1o)),
-        ("seq1o", AvailableComponents::BasicSeq(seq1o)),
-        ("mix1", AvailableComponents::Mixer(mix1)),
-        ("arp1", AvailableComponents::BasicArp(arp1)),
-        ("arp1o", AvailableComponents::BasicArp(arp1o)),
-        ("rvb1", AvailableComponents::BasicReverb(rvb1)),
-    ];
 
-    // Connect the modulation input of the first oscillator to the
-    // output of the second.
-    let wires = vec![
-        //(("wto1", "out"), ("mix1", "a")),
-        // (("wto2", "out"), ("wto1", "modulation")),
-        //(("wto1", "out"), ("wto2", "modulation")),
-        (("adsr1", "out"), ("vca1", "amp_cv")),
-        (("wto1", "out"), ("vca1", "in_cv")),
-        (("seq1", "trigger"), ("adsr1", "trigger")),
-        (("seq1", "gate"), ("adsr1", "gate")),
-        (("vca1", "out"), ("rvb1", "cv_in")),
-        (("seq1", "trigger"), ("arp1", "trigger_in")),
-        (("seq1", "gate"), ("arp1", "gate_in")),
-        (("arp1", "note_cv_out"), ("wto1", "freq")),
-        //(("adsr1o", "out"), ("vca1o", "amp_cv")),
-        //(("wto1o", "out"), ("vca1o", "in_cv")),
-        //(("seq1o", "trigger"), ("adsr1o", "trigger")),
-        //(("seq1o", "gate"), ("adsr1o", "gate")),
-        //(("vca1o", "out"), ("mix1", "b")),
-        //(("seq1o", "trigger"), ("arp1o", "trigger_in")),
-        //(("seq1o", "gate"), ("arp1o", "gate_in")),
-        //(("arp1o", "note_cv_out"), ("wto1o", "freq")),
-    ];
+    tx2.send(transport(&engine)).unwrap();
+    tx2.send(Cmd::DelayedWires(engine.delayed_wires())).unwrap();
+    tx2.send(Cmd::Device(out.describe())).unwrap();
 
-    // Sanity Check of the wires.
-    for (src, dst) in wires.iter() {
-        if let None = components.iter().position(|x| x.0 == src.0) {
-            println!("{} not found for {:?}, {:?}", src.0, src, dst);
-            return None;
-        }
-        if let None = components.iter().position(|x| x.0 == dst.0) {
-            println!("{} not found for {:?}, {:?}", dst.0, src, dst);
-            return None;
-        }
-    }
-    let tempo = 90;
-    let cycles_per_16th = ((60. / ((4 * tempo) as f64)) * (util::RATE as f64)) as u64;
-    let mut cycle_counter = 0;
-    let next_sample = move || -> i16 {
-        match rx.try_recv() {
-            Ok(c) => match c {
-                Cmd::AdsrAttackFor(v) => {
-                    if let Some(j) = components.iter().position(|x| x.0 == "adsr1") {
-                        match &mut components.get_mut(j).unwrap().1 {
-                            AvailableComponents::Adsr(adsr) => {
-                                adsr.attack_for = v;
-                                tx2.send(c).unwrap();
-                            }
-                            _ => (),
-                        }
-                    }
-                }
-                Cmd::AdsrAttackTo(v) => {
-                    if let Some(j) = components.iter().position(|x| x.0 == "adsr1") {
-                        match &mut components.get_mut(j).unwrap().1 {
-                            AvailableComponents::Adsr(adsr) => {
-                                adsr.attack_to = v;
-                                tx2.send(c).unwrap();
-                            }
-                            _ => (),
-                        }
-                    }
-                }
-                Cmd::AdsrDecayFor(v) => {
-                    if let Some(j) = components.iter().position(|x| x.0 == "adsr1") {
-                        match &mut components.get_mut(j).unwrap().1 {
-                            AvailableComponents::Adsr(adsr) => {
-                                adsr.decay_for = v;
-                                tx2.send(c).unwrap();
-                            }
-                            _ => (),
-                        }
+    let (mut to_audio, mut from_control) = RingBuffer::new(MAX_CMDS);
+    let (mut to_control, mut from_audio) = RingBuffer::<Cmd>::new(MAX_CMDS);
+    let (loaded_tx, loaded_rx) = channel();
+    let mut patcher = engine.patcher();
+    let control_tx2 = tx2.clone();
+    thread::spawn(move || {
+        let tx2 = control_tx2;
+        let mut cpu_at = Instant::now();
+        let mut meters_at = Instant::now();
+        loop {
+            match rx.recv_timeout(Duration::from_millis(10)) {
+                Ok(c) => {
+                    if let Some(c) = control(&mut patcher, c, &tx2, &loaded_tx) {
+                        to_audio_thread(&mut to_audio, c, &tx2);
                     }
                 }
-                Cmd::AdsrSustainAt(v) => {
-                    if let Some(j) = components.iter().position(|x| x.0 == "adsr1") {
-                        match &mut components.get_mut(j).unwrap().1 {
-                            AvailableComponents::Adsr(adsr) => {
-                                adsr.sustain_at = v;
-                                tx2.send(c).unwrap();
-                            }
-                            _ => (),
-                        }
-                    }
-                }
-                Cmd::AdsrReleaseFor(v) => {
-                    if let Some(j) = components.iter().position(|x| x.0 == "adsr1") {
-                        match &mut components.get_mut(j).unwrap().1 {
-                            AvailableComponents::Adsr(adsr) => {
-                                adsr.release_for = v;
-                                tx2.send(c).unwrap();
-                            }
-                            _ => (),
-                        }
-                    }
-                }
-                Cmd::Scale(n) => {
-                    if let Some(j) = components.iter().position(|x| x.0 == "arp1") {
-                        match &mut components.get_mut(j).unwrap().1 {
-                            AvailableComponents::BasicArp(arp) => {
-                                arp.notes = n.major_scale();
-                                tx2.send(Cmd::Scale(n)).unwrap();
-                            }
-                            _ => (),
-                        }
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            while let Ok(loaded) = loaded_rx.try_recv() {
+                match loaded {
+                    Ok(table) => to_audio_thread(&mut to_audio, Cmd::WaveTable(table), &tx2),
+                    Err(e) => {
+                        let _ = tx2.send(Cmd::RepatchFailed(e));
                     }
                 }
-                Cmd::FileWaveTable(scwf) => {
-                    if let Some(j) = components.iter().position(|x| x.0 == "wto1") {
-                        match &mut components.get_mut(j).unwrap().1 {
-                            AvailableComponents::WaveTableOsc(wt) => {
-                                wt.which_table = osc::WaveTableOsc::load_scwf(scwf.path);
-                            }
-                            _ => (),
-                        }
+            }
+            if cpu_at.elapsed() >= CPU_EVERY {
+                cpu_at = Instant::now();
+                let cpu = patcher.names().into_iter().map(|x| (x, 0.)).collect();
+                to_audio_thread(&mut to_audio, Cmd::Cpu(cpu), &tx2);
+            }
+            if meters_at.elapsed() >= METERS_EVERY {
+                meters_at = Instant::now();
+                let readings = out::Readings::new(patcher.names());
+                to_audio_thread(&mut to_audio, Cmd::Meters(readings), &tx2);
+            }
+
+            // Anything else sent back is dropped here, rather than on the
+            // audio thread.
+            while let Ok(c) = from_audio.pop() {
+                match c {
+                    Cmd::ParamChanged { .. }
+                    | Cmd::Scale(_)
+                    | Cmd::Cpu(_)
+                    | Cmd::Meters(_)
+                    | Cmd::Transport { .. } => {
+                        let _ = tx2.send(c);
                     }
+                    _ => (),
                 }
-                Cmd::Beat(i, b) => {
-                    beats.write().unwrap()[i as usize] = b;
-                    tx2.send(Cmd::Beat(i, beats.read().unwrap()[i as usize]))
-                        .unwrap();
-                }
-                Cmd::Obeat(i, b) => {
-                    obeats.write().unwrap()[i as usize] = b;
-                    tx2.send(Cmd::Obeat(i, obeats.read().unwrap()[i as usize]))
-                        .unwrap();
-                }
-            },
-            Err(TryRecvError::Empty) => (),
-            Err(TryRecvError::Disconnected) => return 0,
+            }
+            patcher.collect_garbage();
+        }
+    });
+
+    let mut beat_from = engine.output_slot("seq1", "beat");
+    let errors_tx2 = tx2.clone();
+
+    let render = move |out: &mut [i16]| {
+        while let Ok(c) = from_control.pop() {
+            if let Some(c) = apply(&mut engine, c) {
+                // Only if the control thread has stopped keeping up is
+                // anything dropped here.
+                let _ = to_control.push(c);
+            }
+        }
+        if from_control.is_abandoned() {
+            out.iter_mut().for_each(|x| *x = 0);
+            return;
         }
-        let mut tick = false;
-        cycle_counter += 1;
-        if cycle_counter >= cycles_per_16th {
-            cycle_counter = 0;
-            tick = true;
-            //if let Some(j) = components.iter().position(|x| x.0 == dst.0) {
-            //    components[j].1[dst.1] = components[i].1[src.1];
-            //}
+
+        if engine.swap_graph() {
+            beat_from = engine.output_slot("seq1", "beat");
         }
+        engine.render(out);
 
-        for component in components.iter_mut() {
-            component.1.step();
+        if let Some(beat) = beat_from {
+            setbeat.store(engine.output(beat), Ordering::Relaxed);
         }
+    };
+    out.play(render, move |e| {
+        let _ = errors_tx2.send(Cmd::DeviceError(e));
+    })?;
+    Ok(out)
+}
 
-        if tick {
-            if let Some(j) = components.iter().position(|x| x.0 == "seq1") {
-                setbeat.store(components[j].1["beat"], Ordering::Relaxed);
+// Carries out a command on the audio thread, and returns what to send
+// back. Commands are sent back even when there's nothing to report, if they
+// hold anything that would have to be freed.
+fn apply(engine: &mut Engine, c: Cmd) -> Option<Cmd> {
+    match c {
+        Cmd::SetParam {
+            component,
+            port,
+            value,
+        } => Some(
+            match engine.schedule_set(engine.now(), &component, &port, value) {
+                Some(value) => Cmd::ParamChanged {
+                    component,
+                    port,
+                    value,
+                },
+                None => Cmd::SetParam {
+                    component,
+                    port,
+                    value,
+                },
+            },
+        ),
+        Cmd::Scale(n) => {
+            if let Some(AvailableComponents::BasicArp(arp)) = engine.component_mut("arp1") {
+                arp.notes = n.major_scale();
+                return Some(Cmd::Scale(n));
             }
-            for component in components.iter_mut() {
-                component.1.tick();
+            None
+        }
+        Cmd::NoteOn(note) => {
+            engine.schedule_note(engine.now(), note, true);
+            None
+        }
+        Cmd::NoteOff(note) => {
+            engine.schedule_note(engine.now(), note, false);
+            None
+        }
+        // The table that's replaced goes back to be dropped.
+        Cmd::WaveTable(table) => match engine.component_mut("wto1") {
+            Some(AvailableComponents::WaveTableOsc(wt)) => {
+                Some(Cmd::WaveTable(mem::replace(&mut wt.which_table, table)))
             }
+            _ => Some(Cmd::WaveTable(table)),
+        },
+        Cmd::Cpu(mut cpu) => {
+            engine.take_cpu(&mut cpu);
+            Some(Cmd::Cpu(cpu))
+        }
+        Cmd::Meters(mut readings) => {
+            engine.take_meters(&mut readings);
+            Some(Cmd::Meters(readings))
+        }
+        Cmd::ClearClips => {
+            engine.clear_clips();
+            None
         }
+        Cmd::Play => {
+            engine.transport_mut().play();
+            Some(transport(engine))
+        }
+        Cmd::Pause => {
+            engine.transport_mut().pause();
+            Some(transport(engine))
+        }
+        Cmd::Stop => {
+            engine.stop();
+            Some(transport(engine))
+        }
+        Cmd::Locate(step) => {
+            engine.locate(step);
+            Some(transport(engine))
+        }
+        Cmd::Tempo(bpm) => {
+            engine.transport_mut().set_bpm(bpm);
+            Some(transport(engine))
+        }
+        Cmd::Swing(swing) => {
+            engine.transport_mut().set_swing(swing);
+            Some(transport(engine))
+        }
+        // The port the scope was on goes back to be dropped.
+        Cmd::Scope(port) => Some(Cmd::Scope(engine.tap(port))),
+        // As with wavetables, the groove that's replaced goes back.
+        Cmd::Groove(groove) => Some(Cmd::Groove(engine.transport_mut().set_groove(groove))),
+        c => Some(c),
+    }
+}
+
+fn transport(engine: &Engine) -> Cmd {
+    Cmd::Transport {
+        state: engine.transport().state(),
+        bpm: engine.transport().bpm(),
+        swing: engine.transport().swing(),
+    }
+}
 
-        for (src, dst) in wires.iter() {
-            if let Some(i) = components.iter().position(|x| x.0 == src.0) {
-                if let Some(j) = components.iter().position(|x| x.0 == dst.0) {
-                    components[j].1[dst.1] = components[i].1[src.1];
+// Queues a command for the audio thread. The queue only fills up if the
+// audio thread has stopped, in which case the command is dropped.
+fn to_audio_thread(to_audio: &mut Producer<Cmd>, c: Cmd, tx2: &Sender<Cmd>) {
+    if let Err(PushError::Full(_)) = to_audio.push(c) {
+        let _ = tx2.send(Cmd::RepatchFailed(
+            "the audio thread isn't taking commands".to_string(),
+        ));
+    }
+}
+
+// Carries out the commands that are done off the audio thread: repatching,
+// changing the sequencers' patterns, and loading wavetables, which are
+// sent through `loaded` once they're read, and grooves, which are small
+// enough to read here. Returns the others.
+fn control(
+    patcher: &mut Patcher,
+    c: Cmd,
+    tx2: &Sender<Cmd>,
+    loaded: &Sender<Result<osc::WaveTableChoice, String>>,
+) -> Option<Cmd> {
+    let result = match c {
+        Cmd::AddComponent { name, kind, args } => {
+            let args = args.iter().map(|x| x.as_str()).collect::<Vec<&str>>();
+            patcher.add(&name, &kind, &args).map(|params| {
+                for ((component, port), value) in params {
+                    let _ = tx2.send(Cmd::ParamChanged {
+                        component,
+                        port,
+                        value,
+                    });
                 }
+            })
+        }
+        Cmd::RemoveComponent(name) => patcher.remove(&name),
+        Cmd::Connect(src, dst) => patcher.connect(src, dst),
+        Cmd::Disconnect(src, dst) => patcher.disconnect(src, dst),
+        Cmd::Beat(i, b) | Cmd::Obeat(i, b) => {
+            let seq = if let Cmd::Beat(..) = c {
+                "seq1"
+            } else {
+                "seq1o"
+            };
+            if let Some(pattern) = patcher.pattern(seq) {
+                pattern.set(i as usize, b);
+                let _ = tx2.send(c);
             }
+            return None;
         }
-        if let Some(j) = components.iter().position(|x| x.0 == "rvb1") {
-            components[j].1["out"]
-        } else {
-            0
+        // The UI is told which port the scope is on once it's checked.
+        Cmd::Scope(port) => {
+            if let Some(port) = &port {
+                if let Err(e) = patcher.check_output(port) {
+                    let _ = tx2.send(Cmd::RepatchFailed(e.to_string()));
+                    return None;
+                }
+            }
+            let _ = tx2.send(Cmd::Scope(port.clone()));
+            return Some(Cmd::Scope(port));
+        }
+        Cmd::LoadGroove(path) => {
+            return match path.map_or(Ok(Groove::straight()), |x| Groove::load(&x)) {
+                Ok(groove) => Some(Cmd::Groove(groove)),
+                Err(e) => {
+                    let _ = tx2.send(Cmd::RepatchFailed(e));
+                    None
+                }
+            };
+        }
+        Cmd::FileWaveTable(scwf) => {
+            let loaded = loaded.clone();
+            thread::spawn(move || {
+                let _ = loaded.send(osc::WaveTableOsc::load_scwf(&scwf.path));
+            });
+            return None;
         }
+        c => return Some(c),
     };
-    Some(out::CpalOut::from_defaults(next_sample).unwrap())
+    // The UI may already have gone, in which case there's no one to tell.
+    let _ = tx2.send(match result {
+        Ok(()) => Cmd::DelayedWires(patcher.delayed_wires()),
+        Err(e) => Cmd::RepatchFailed(e.to_string()),
+    });
+    None
 }
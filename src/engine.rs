@@ -0,0 +1,923 @@
+use std::array;
+use std::mem;
+use std::ops::Range;
+use std::time::Instant;
+
+use rtrb::Consumer;
+use rtrb::Producer;
+use rtrb::RingBuffer;
+
+use crate::audio::AvailableComponents;
+use crate::event::Action;
+use crate::event::Event;
+use crate::event::Events;
+use crate::graph::Schedule;
+use crate::graph::Wire;
+use crate::out::Meter;
+use crate::out::Readings;
+use crate::patch::Patch;
+use crate::patch::PortRef;
+use crate::patcher::Patcher;
+use crate::transport::Tick;
+use crate::transport::Transport;
+use crate::util::Component;
+use crate::util::PortInfo;
+use crate::util::PortKind;
+
+// The most samples rendered in one pass over the graph.
+pub const MAX_BLOCK: usize = 512;
+// The channels of a frame: left and right.
+pub const CHANNELS: usize = 2;
+// The most input, or output, ports a component can have.
+pub const MAX_PORTS: usize = 16;
+// The most repatches that can be waiting for the audio thread to pick
+// them up.
+const MAX_PENDING: usize = 16;
+
+// A component and its name. They're boxed so that moving them from one
+// graph to the next doesn't copy them.
+pub type Node = Box<(String, AvailableComponents)>;
+
+// The name and ports of a component: all that's needed to build a Graph.
+#[derive(Clone)]
+pub struct Layout {
+    pub name: String,
+    pub inputs: &'static [PortInfo],
+    pub outputs: &'static [PortInfo],
+}
+
+impl Layout {
+    pub fn of(name: &str, c: &dyn Component) -> Layout {
+        Layout {
+            name: name.to_string(),
+            inputs: c.input_info(),
+            outputs: c.output_info(),
+        }
+    }
+}
+
+// Everything about a patch that's worked out from its wires, rather than
+// being state of the components. A new one is built off the audio thread
+// whenever the patch changes.
+pub struct Graph {
+    wires: Vec<(PortRef, PortRef)>,
+    schedule: Schedule,
+    // The number of input and output slots of each component.
+    ports: Vec<(usize, usize)>,
+    // The samples of each output slot of each component, for the block
+    // being rendered.
+    buffers: Vec<Vec<Vec<i16>>>,
+    // The output slot played on each channel.
+    out: [(usize, usize); CHANNELS],
+    // Nanoseconds spent in each component since the last `take_cpu`.
+    cpu: Vec<u64>,
+}
+
+impl Graph {
+    // The names in `wires` and `out` must have already been checked
+    // against `layout`.
+    pub fn new(
+        layout: &[Layout],
+        wires: Vec<(PortRef, PortRef)>,
+        out: &[PortRef; CHANNELS],
+    ) -> Graph {
+        // Resolve the names in the wires to indices once, here, rather
+        // than every sample.
+        let index_of = |name: &str| layout.iter().position(|x| x.name == name).unwrap();
+        let slot_of =
+            |ports: &[PortInfo], name: &str| ports.iter().position(|x| x.name == name).unwrap();
+        let compiled = wires
+            .iter()
+            .map(|(src, dst)| {
+                let (src_i, dst_i) = (index_of(&src.0), index_of(&dst.0));
+                Wire::new(
+                    src_i,
+                    slot_of(layout[src_i].outputs, &src.1),
+                    dst_i,
+                    slot_of(layout[dst_i].inputs, &dst.1),
+                )
+            })
+            .collect::<Vec<Wire>>();
+        let schedule = Schedule::new(layout.len(), &compiled);
+
+        let out = array::from_fn(|c| {
+            let i = index_of(&out[c].0);
+            (i, slot_of(layout[i].outputs, &out[c].1))
+        });
+
+        let ports = layout
+            .iter()
+            .map(|x| {
+                let ports = (x.inputs.len(), x.outputs.len());
+                assert!(
+                    ports.0 <= MAX_PORTS && ports.1 <= MAX_PORTS,
+                    "{} has more than {} ports",
+                    x.name,
+                    MAX_PORTS
+                );
+                ports
+            })
+            .collect::<Vec<(usize, usize)>>();
+        let buffers = ports
+            .iter()
+            .map(|(_, outputs)| vec![vec![0; MAX_BLOCK]; *outputs])
+            .collect();
+
+        Graph {
+            wires,
+            schedule,
+            ports,
+            buffers,
+            out,
+            cpu: vec![0; layout.len()],
+        }
+    }
+
+    pub fn delayed_wires(&self) -> Vec<(PortRef, PortRef)> {
+        self.schedule
+            .delayed
+            .iter()
+            .map(|w| self.wires[*w].clone())
+            .collect()
+    }
+}
+
+// A new graph, and the components to go with it. Components that are
+// kept move from their old slot to a new one, as listed in `moves`; the
+// other slots of `nodes` hold the components being added.
+//
+// Once the audio thread has swapped it in, the Update holds the old graph
+// and any components that were removed, and is sent back so that they can
+// be dropped off the audio thread.
+pub struct Update {
+    pub graph: Graph,
+    pub nodes: Vec<Option<Node>>,
+    pub moves: Vec<(usize, usize)>,
+}
+
+// Runs the components of a patch, a block of samples at a time.
+pub struct Engine {
+    // Only ever None part way through swapping in an Update.
+    nodes: Vec<Option<Node>>,
+    graph: Graph,
+    // From and back to the Patcher, once there is one.
+    updates: Option<Consumer<Box<Update>>>,
+    garbage: Option<Producer<Box<Update>>>,
+    rate: u32,
+    transport: Transport,
+    // Offsets, into the block being rendered, of the samples where the
+    // 16th note clock ticks.
+    ticks: Vec<Tick>,
+    // Frames rendered since the last `take_cpu`.
+    rendered: u64,
+    // The next sample to render, counted from the start.
+    now: u64,
+    events: Events,
+    // The port the scope is on, with its index and slot while it's in the
+    // graph, or None for the master output, and where its samples go once
+    // there's a scope.
+    tap: Option<(PortRef, Option<(usize, usize)>)>,
+    scope: Option<Producer<i16>>,
+    // The levels of the output, a channel each.
+    master: [Meter; CHANNELS],
+}
+
+impl Engine {
+    pub fn new(patch: Patch, tempo: u64) -> Engine {
+        let Patch {
+            components,
+            wires,
+            out,
+            rate,
+        } = patch;
+
+        let layout = components
+            .iter()
+            .map(|(name, c)| Layout::of(name, c.as_component()))
+            .collect::<Vec<Layout>>();
+        let graph = Graph::new(&layout, wires, &out);
+
+        Engine {
+            nodes: components.into_iter().map(|x| Some(Box::new(x))).collect(),
+            graph,
+            updates: None,
+            garbage: None,
+            rate,
+            transport: Transport::new(tempo as f64, rate),
+            ticks: Vec::with_capacity(MAX_BLOCK),
+            rendered: 0,
+            now: 0,
+            events: Events::new(),
+            tap: None,
+            scope: None,
+            master: Default::default(),
+        }
+    }
+
+    // Hands out a Patcher for changing the patch while it plays. Its
+    // changes are picked up by swap_graph.
+    pub fn patcher(&mut self) -> Patcher {
+        let (updates_tx, updates_rx) = RingBuffer::new(MAX_PENDING);
+        let (garbage_tx, garbage_rx) = RingBuffer::new(MAX_PENDING);
+        self.updates = Some(updates_rx);
+        self.garbage = Some(garbage_tx);
+
+        let layout = self
+            .nodes()
+            .map(|(name, c)| Layout::of(name, c.as_component()))
+            .collect();
+        let out = self.graph.out.map(|(i, slot)| {
+            (
+                self.nodes().nth(i).unwrap().0.clone(),
+                component_at(&self.nodes, i).output_info()[slot]
+                    .name
+                    .to_string(),
+            )
+        });
+        let mut patcher = Patcher::new(
+            layout,
+            self.graph.wires.clone(),
+            out,
+            self.graph.delayed_wires(),
+            updates_tx,
+            garbage_rx,
+            self.rate,
+        );
+        for (name, c) in self.nodes() {
+            patcher.keep_pattern(name, c);
+        }
+        patcher
+    }
+
+    // Hands out the end of a ring that the samples of the tapped port are
+    // copied into, half a second's worth, for a scope to draw. Samples
+    // that don't fit are dropped.
+    pub fn scope(&mut self) -> Consumer<i16> {
+        let (tx, rx) = RingBuffer::new(self.rate as usize / 2);
+        self.scope = Some(tx);
+        rx
+    }
+
+    // Points the scope at an output, or at the master output. Returns the port it
+    // was on, so that it can be dropped somewhere other than the audio
+    // thread.
+    pub fn tap(&mut self, port: Option<PortRef>) -> Option<PortRef> {
+        let old = self.tap.take().map(|x| x.0);
+        self.tap = port.map(|port| {
+            let from = self.output_slot(&port.0, &port.1);
+            (port, from)
+        });
+        old
+    }
+
+    // Swaps in any new graphs from the Patcher. Returns whether the graph
+    // changed, and so whether any indices from output_slot are out of
+    // date.
+    pub fn swap_graph(&mut self) -> bool {
+        let (updates, garbage) = match (&mut self.updates, &mut self.garbage) {
+            (Some(updates), Some(garbage)) => (updates, garbage),
+            _ => return false,
+        };
+        let mut swapped = false;
+        // Only take an update if the old graph can be sent back, so that
+        // nothing is dropped here.
+        while garbage.slots() > 0 {
+            let mut update = match updates.pop() {
+                Ok(x) => x,
+                Err(_) => break,
+            };
+            for (from, to) in update.moves.iter() {
+                update.nodes[*to] = self.nodes[*from].take();
+                update.graph.cpu[*to] = self.graph.cpu[*from];
+            }
+            mem::swap(&mut self.nodes, &mut update.nodes);
+            mem::swap(&mut self.graph, &mut update.graph);
+            self.events.retarget(&update.moves);
+            let _ = garbage.push(update);
+            swapped = true;
+        }
+        if swapped {
+            if let Some((port, _)) = self.tap.take() {
+                self.tap(Some(port));
+            }
+        }
+        swapped
+    }
+
+    pub fn delayed_wires(&self) -> Vec<(PortRef, PortRef)> {
+        self.graph.delayed_wires()
+    }
+
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    // Samples in a bar of 16 16ths, as the clock counts them.
+    pub fn bar_len(&self) -> usize {
+        16 * self.transport.cycles_per_16th() as usize
+    }
+
+    pub fn transport(&self) -> &Transport {
+        &self.transport
+    }
+
+    // For playing, pausing and changing the tempo. Locating and stopping
+    // also move the components, with `locate` and `stop`.
+    pub fn transport_mut(&mut self) -> &mut Transport {
+        &mut self.transport
+    }
+
+    // Makes `step` the next step to play, for the transport and for every
+    // component.
+    pub fn locate(&mut self, step: u64) {
+        self.transport.locate(step);
+        self.locate_components(step);
+    }
+
+    // Stopping the transport locates it to the start, so only the
+    // components are left to follow it.
+    pub fn stop(&mut self) {
+        self.transport.stop();
+        self.locate_components(0);
+    }
+
+    fn locate_components(&mut self, step: u64) {
+        for node in self.nodes.iter_mut() {
+            node.as_mut().unwrap().1.as_component_mut().locate(step);
+        }
+    }
+
+    fn nodes(&self) -> impl Iterator<Item = &(String, AvailableComponents)> {
+        self.nodes.iter().map(|x| x.as_deref().unwrap())
+    }
+
+    pub fn component(&self, name: &str) -> Option<&AvailableComponents> {
+        self.nodes().find(|x| x.0 == name).map(|x| &x.1)
+    }
+
+    pub fn component_mut(&mut self, name: &str) -> Option<&mut AvailableComponents> {
+        self.nodes
+            .iter_mut()
+            .map(|x| x.as_deref_mut().unwrap())
+            .find(|x| x.0 == name)
+            .map(|x| &mut x.1)
+    }
+
+    // The index and slot of an output, for reading with `output`. These
+    // change when the graph is swapped.
+    pub fn output_slot(&self, component: &str, port: &str) -> Option<(usize, usize)> {
+        let i = self.nodes().position(|x| x.0 == component)?;
+        let slot = component_at(&self.nodes, i).output_slot(port).ok()?;
+        Some((i, slot))
+    }
+
+    pub fn output(&self, (i, slot): (usize, usize)) -> i16 {
+        component_at(&self.nodes, i).output(slot)
+    }
+
+    // The next sample to be rendered, counted from the start, for
+    // scheduling events.
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    // Schedules an input to be set just before sample `at` is rendered,
+    // clamped to the port's range, and returns the value it will be set
+    // to. Events that are already late are delivered at the start of the
+    // next block. None if there's no such input or no room for the event.
+    //
+    // A wired input is overwritten by its wire. Setting a trigger to
+    // anything but 0 is two events: that value, and 0 a sample later, so
+    // there has to be room for both.
+    pub fn schedule_set(
+        &mut self,
+        at: u64,
+        component: &str,
+        port: &str,
+        value: i16,
+    ) -> Option<i16> {
+        let i = self.nodes().position(|x| x.0 == component)?;
+        let c = component_at(&self.nodes, i);
+        let slot = c.input_slot(port).ok()?;
+        let info = &c.input_info()[slot];
+        let value = value.clamp(info.min, info.max);
+        let pulse = info.kind == PortKind::Trigger && value != 0;
+        if self.events.room() < 1 + pulse as usize {
+            return None;
+        }
+        let action = Action::Set { slot, value };
+        self.events
+            .push(Event {
+                at,
+                target: i,
+                action,
+            })
+            .ok()?;
+        if pulse {
+            let action = Action::Set { slot, value: 0 };
+            self.events
+                .push(Event {
+                    at: at + 1,
+                    target: i,
+                    action,
+                })
+                .ok()?;
+        }
+        Some(value)
+    }
+
+    // Schedules a note on, or off, for every Voices component. Returns
+    // whether there was room for them all.
+    pub fn schedule_note(&mut self, at: u64, note: i16, on: bool) -> bool {
+        let action = if on {
+            Action::NoteOn(note)
+        } else {
+            Action::NoteOff(note)
+        };
+        let mut room = true;
+        for (target, node) in self.nodes.iter().enumerate() {
+            if let Some(node) = node {
+                if let AvailableComponents::Voices(_) = node.1 {
+                    room &= self.events.push(Event { at, target, action }).is_ok();
+                }
+            }
+        }
+        room
+    }
+
+    // The current value of every input of every component.
+    pub fn params(&mut self) -> Vec<(PortRef, i16)> {
+        let mut params = vec![];
+        for node in self.nodes.iter_mut() {
+            let (name, c) = &mut **node.as_mut().unwrap();
+            params.extend(inputs_of(name, c.as_component_mut()));
+        }
+        params
+    }
+
+    // Fills in the levels of the master and of the mixers, matching the
+    // names like take_cpu does, and starts the peaks and RMS over.
+    pub fn take_meters(&mut self, readings: &mut Readings) {
+        for (r, meter) in readings.master.iter_mut().zip(self.master.iter_mut()) {
+            *r = meter.take();
+        }
+        for (i, (name, r)) in readings.mixers.iter_mut().enumerate() {
+            match self.nodes.get_mut(i) {
+                Some(Some(node)) if node.0 == *name => {
+                    if let AvailableComponents::Mixer(mixer) = &mut node.1 {
+                        *r = Some(mixer.meters.each_mut().map(Meter::take));
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    // The levels since the start, or since the last reading, for when
+    // allocating doesn't matter.
+    pub fn readings(&mut self) -> Readings {
+        let mut readings = Readings::new(self.nodes().map(|x| x.0.clone()).collect());
+        self.take_meters(&mut readings);
+        readings
+    }
+
+    pub fn clear_clips(&mut self) {
+        self.master.iter_mut().for_each(|x| x.clear_clips());
+        for node in self.nodes.iter_mut() {
+            if let AvailableComponents::Mixer(mixer) = &mut node.as_mut().unwrap().1 {
+                mixer.meters.iter_mut().for_each(|x| x.clear_clips());
+            }
+        }
+    }
+
+    // Fills in the share of the real-time budget each component in `cpu`
+    // has taken since the last call, 1 being all of it: how long it took to
+    // render its samples over how long they take to play. The names come
+    // from the Patcher, in the order of its latest graph, and any that
+    // don't match the component at the same index here are left alone, so
+    // nothing is allocated.
+    pub fn take_cpu(&mut self, cpu: &mut [(String, f32)]) {
+        let budget = self.rendered as f32 * 1e9 / self.rate as f32;
+        for (i, (name, x)) in cpu.iter_mut().enumerate() {
+            match self.nodes.get(i) {
+                Some(Some(node)) if node.0 == *name => {
+                    *x = self.graph.cpu[i] as f32 / budget.max(1.)
+                }
+                _ => (),
+            }
+        }
+        self.graph.cpu.iter_mut().for_each(|x| *x = 0);
+        self.rendered = 0;
+    }
+
+    // Fills `out` with frames of CHANNELS samples, left first.
+    pub fn render(&mut self, out: &mut [i16]) {
+        for chunk in out.chunks_mut(MAX_BLOCK * CHANNELS) {
+            let len = chunk.len() / CHANNELS;
+            self.rendered += len as u64;
+            self.render_block(len);
+            for (c, (i, slot)) in self.graph.out.iter().enumerate() {
+                let samples = &self.graph.buffers[*i][*slot][..len];
+                for (frame, s) in chunk.chunks_mut(CHANNELS).zip(samples) {
+                    frame[c] = *s;
+                    self.master[c].add(*s);
+                }
+            }
+            if let Some(scope) = &mut self.scope {
+                match &self.tap {
+                    Some((_, Some((i, slot)))) => {
+                        for s in self.graph.buffers[*i][*slot][..len].iter() {
+                            if scope.push(*s).is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    Some((_, None)) => (),
+                    // The master output, mixed down to mono.
+                    None => {
+                        for frame in chunk.chunks(CHANNELS) {
+                            let s = frame.iter().map(|x| *x as i32).sum::<i32>() / CHANNELS as i32;
+                            if scope.push(s as i16).is_err() {
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn render_block(&mut self, len: usize) {
+        self.transport.ticks(len, &mut self.ticks);
+
+        let mut p = 0;
+        let mut f = 0;
+        while p < self.graph.schedule.order.len() {
+            match self.graph.schedule.feedback.get(f) {
+                Some(r) if r.start == p => {
+                    let r = r.clone();
+                    self.step_together(r.clone(), len);
+                    p = r.end;
+                    f += 1;
+                }
+                _ => {
+                    self.process_one(p, len);
+                    p += 1;
+                }
+            }
+        }
+
+        let end = self.now + len as u64;
+        self.events.remove_due(end);
+        self.now = end;
+    }
+
+    // Processes the component at position `p` of the order over the whole
+    // block, breaking the block up at the clock ticks and at its events.
+    fn process_one(&mut self, p: usize, len: usize) {
+        let started = Instant::now();
+        let g = &mut self.graph;
+        let (i, wires) = &g.schedule.order[p];
+        let (i, n_in) = (*i, g.ports[*i].0);
+
+        let mut own = mem::take(&mut g.buffers[i]);
+        let mut ins: [Option<&[i16]>; MAX_PORTS] = [None; MAX_PORTS];
+        for w in g.schedule.wires[wires.clone()].iter() {
+            ins[w.in_slot] = Some(&g.buffers[w.src][w.out_slot][..len]);
+        }
+
+        let node = &mut self.nodes[i].as_mut().unwrap().1;
+        let now = self.now;
+        let mut events = self
+            .events
+            .due(now + len as u64)
+            .iter()
+            .filter(|e| e.target == i)
+            .peekable();
+        let mut ticks = self.ticks.iter().peekable();
+        let mut start = 0;
+        loop {
+            // The offsets of the next event, which comes before its sample,
+            // and of the next tick, which comes after its own.
+            let event = events
+                .peek()
+                .map_or(usize::MAX, |e| e.at.saturating_sub(now) as usize);
+            let tick = ticks.peek().map_or(usize::MAX, |t| t.at + 1);
+            if event == usize::MAX && tick == usize::MAX {
+                break;
+            } else if event < tick {
+                process_range(
+                    node.as_component_mut(),
+                    &ins[..n_in],
+                    &mut own,
+                    start..event,
+                );
+                events.next().unwrap().action.apply(node);
+                start = event;
+            } else {
+                let component = node.as_component_mut();
+                process_range(component, &ins[..n_in], &mut own, start..tick);
+                component.tick(ticks.next().unwrap().velocity);
+                for (slot, buffer) in own.iter_mut().enumerate() {
+                    buffer[tick - 1] = component.output(slot);
+                }
+                start = tick;
+            }
+        }
+        process_range(node.as_component_mut(), &ins[..n_in], &mut own, start..len);
+
+        g.buffers[i] = own;
+        g.cpu[i] += started.elapsed().as_nanos() as u64;
+    }
+
+    // Steps the components at the positions in `r` one sample at a time,
+    // so that the delayed wires between them are only a sample late.
+    fn step_together(&mut self, r: Range<usize>, len: usize) {
+        let started = Instant::now();
+        let g = &mut self.graph;
+        let now = self.now;
+        let due = self.events.due(now + len as u64);
+        let mut e = 0;
+        let mut t = 0;
+        for n in 0..len {
+            let tick = self.ticks.get(t).filter(|x| x.at == n).copied();
+            if tick.is_some() {
+                t += 1;
+            }
+            while e < due.len() && due[e].at.saturating_sub(now) as usize <= n {
+                let target = due[e].target;
+                if g.schedule.order[r.clone()]
+                    .iter()
+                    .any(|(i, _)| *i == target)
+                {
+                    due[e]
+                        .action
+                        .apply(&mut self.nodes[target].as_mut().unwrap().1);
+                }
+                e += 1;
+            }
+            for (i, wires) in g.schedule.order[r.clone()].iter() {
+                for w in g.schedule.wires[wires.clone()].iter() {
+                    let v = if w.delayed {
+                        component_at(&self.nodes, w.src).output(w.out_slot)
+                    } else {
+                        g.buffers[w.src][w.out_slot][n]
+                    };
+                    *component_at_mut(&mut self.nodes, *i).input_mut(w.in_slot) = v;
+                }
+                let component = component_at_mut(&mut self.nodes, *i);
+                component.step();
+                if let Some(tick) = tick {
+                    component.tick(tick.velocity);
+                }
+                for (slot, buffer) in g.buffers[*i].iter_mut().enumerate() {
+                    buffer[n] = component.output(slot);
+                }
+            }
+        }
+        // Timing each step would cost more than the steps, so the
+        // components share the time evenly.
+        let each = started.elapsed().as_nanos() as u64 / r.len() as u64;
+        for (i, _) in g.schedule.order[r].iter() {
+            g.cpu[*i] += each;
+        }
+    }
+}
+
+// The current value of each input of a component.
+pub fn inputs_of(name: &str, c: &mut dyn Component) -> Vec<(PortRef, i16)> {
+    let info = c.input_info();
+    (0..info.len())
+        .map(|slot| {
+            (
+                (name.to_string(), info[slot].name.to_string()),
+                *c.input_mut(slot),
+            )
+        })
+        .collect()
+}
+
+fn component_at(nodes: &[Option<Node>], i: usize) -> &dyn Component {
+    nodes[i].as_ref().unwrap().1.as_component()
+}
+
+fn component_at_mut(nodes: &mut [Option<Node>], i: usize) -> &mut dyn Component {
+    nodes[i].as_mut().unwrap().1.as_component_mut()
+}
+
+fn process_range(
+    component: &mut dyn Component,
+    ins: &[Option<&[i16]>],
+    outs: &mut [Vec<i16>],
+    r: Range<usize>,
+) {
+    let (n_in, n_out) = (ins.len(), outs.len());
+    let ins: [Option<&[i16]>; MAX_PORTS] =
+        array::from_fn(|s| ins.get(s).copied().flatten().map(|x| &x[r.clone()]));
+    let mut outs = outs.iter_mut().map(|x| &mut x[r.clone()]);
+    let mut outs: [&mut [i16]; MAX_PORTS] = array::from_fn(|_| outs.next().unwrap_or(&mut []));
+    component.process(r.len(), &ins[..n_in], &mut outs[..n_out]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::DEFAULT_RATE;
+
+    fn engine(patch: &str) -> Engine {
+        let mut engine = Engine::new(Patch::parse(patch, DEFAULT_RATE).unwrap(), 480);
+        engine.transport_mut().play();
+        engine
+    }
+
+    #[test]
+    fn wires_are_resolved_to_slots() {
+        let engine = engine(
+            "wto1 = WaveTableOsc
+             mix1 = Mixer
+             vca1 = Vca
+             wto1.out -> mix1.b
+             mix1.out -> vca1.in_cv
+             out vca1.out mix1.out",
+        );
+        let g = &engine.graph;
+        let wires = g
+            .schedule
+            .wires
+            .iter()
+            .map(|w| (w.src, w.out_slot, w.dst, w.in_slot))
+            .collect::<Vec<(usize, usize, usize, usize)>>();
+        // mix1.b is the Mixer's third input, and vca1.in_cv the Vca's
+        // second.
+        assert_eq!(wires, [(0, 0, 1, 2), (1, 0, 2, 1)]);
+        assert_eq!(g.out, [(2, 0), (1, 0)]);
+        assert_eq!(g.ports, [(3, 1), (4, 1), (2, 1)]);
+    }
+
+    // The same patch, rendered a block at a time, a few frames at a time,
+    // and with every component stepped a sample at a time as if they were
+    // all in a feedback loop, sounds the same.
+    #[test]
+    fn blocks_render_like_single_samples() {
+        let patch = "seq1 = BasicSeq beats=0,2,5,7 beat_len=300
+                     adsr1 = Adsr
+                     adsr1.attack_for = 256
+                     adsr1.attack_to = 32767
+                     adsr1.decay_for = 256
+                     adsr1.sustain_at = 16383
+                     adsr1.release_for = 512
+                     wto1 = WaveTableOsc table=saw
+                     wto1.freq = 57
+                     vca1 = Vca
+                     wto2 = WaveTableOsc table=square
+                     mix1 = Mixer
+                     seq1.trigger -> adsr1.trigger
+                     seq1.gate -> adsr1.gate
+                     adsr1.out -> vca1.amp_cv
+                     wto1.out -> vca1.in_cv
+                     vca1.out -> mix1.a
+                     wto2.out -> mix1.b
+                     out mix1.out vca1.out";
+        // A few 16ths, and a block that isn't full.
+        let len = 8 * MAX_BLOCK + 77;
+
+        let mut blocks = vec![0; len * CHANNELS];
+        engine(patch).render(&mut blocks);
+        assert!(blocks.iter().skip(1).step_by(CHANNELS).any(|x| *x != 0));
+
+        let mut frames = vec![0; len * CHANNELS];
+        let mut e = engine(patch);
+        for chunk in frames.chunks_mut(13 * CHANNELS) {
+            e.render(chunk);
+        }
+        assert_eq!(frames, blocks);
+
+        let mut samples = vec![0; len * CHANNELS];
+        let mut e = engine(patch);
+        let all = 0..e.graph.schedule.order.len();
+        e.graph.schedule.feedback = vec![all];
+        e.render(&mut samples);
+        assert_eq!(samples, blocks);
+    }
+
+    #[test]
+    fn unknown_ports_are_errors() {
+        let mut engine = engine("vca1 = Vca\nout vca1.out");
+        let vca = engine.component("vca1").unwrap().as_component();
+        assert_eq!(vca.input_slot("in_cv"), Ok(1));
+        let err = vca.input_slot("in").unwrap_err();
+        assert_eq!(err.valid, ["amp_cv", "in_cv"]);
+        assert_eq!(
+            err.to_string(),
+            "no input `in` (expected one of: amp_cv, in_cv)"
+        );
+        assert_eq!(vca.output_slot("in_cv").unwrap_err().dir, "output");
+
+        assert_eq!(engine.output_slot("vca1", "out"), Some((0, 0)));
+        assert_eq!(engine.output_slot("vca1", "in_cv"), None);
+        assert_eq!(engine.output_slot("vca2", "out"), None);
+        assert_eq!(engine.schedule_set(0, "vca1", "nope", 1), None);
+        assert_eq!(engine.schedule_set(0, "vca2", "in_cv", 1), None);
+    }
+
+    fn param(engine: &mut Engine, component: &str, port: &str) -> i16 {
+        let port = (component.to_string(), port.to_string());
+        engine.params().into_iter().find(|x| x.0 == port).unwrap().1
+    }
+
+    #[test]
+    fn set_params_are_clamped_to_their_range() {
+        let mut engine = engine("wto1 = WaveTableOsc\nout wto1.out");
+        assert_eq!(engine.schedule_set(0, "wto1", "freq", 200), Some(127));
+        assert_eq!(engine.schedule_set(10, "wto1", "freq", -5), Some(0));
+        let mut out = vec![0; 8 * CHANNELS];
+        engine.render(&mut out);
+        assert_eq!(param(&mut engine, "wto1", "freq"), 127);
+        engine.render(&mut out);
+        assert_eq!(param(&mut engine, "wto1", "freq"), 0);
+    }
+
+    #[test]
+    fn triggers_are_reset_a_sample_later() {
+        let mut engine = engine("env1 = Adsr\nout env1.out");
+        assert_eq!(engine.schedule_set(4, "env1", "trigger", 1000), Some(1000));
+        let mut out = vec![0; 5 * CHANNELS];
+        engine.render(&mut out);
+        assert_eq!(param(&mut engine, "env1", "trigger"), 1000);
+        engine.render(&mut out[..CHANNELS]);
+        assert_eq!(param(&mut engine, "env1", "trigger"), 0);
+    }
+
+    // Shortening a stage of an envelope while it's in that stage moves it
+    // on to the next.
+    #[test]
+    fn stages_can_be_shortened_while_they_run() {
+        let mut engine = engine(
+            "seq1 = BasicSeq beats=1 beat_len=max
+             adsr1 = Adsr
+             adsr1.attack_for = 2000
+             adsr1.attack_to = 32767
+             adsr1.decay_for = 2000
+             adsr1.sustain_at = 16383
+             adsr1.release_for = 2000
+             seq1.trigger -> adsr1.trigger
+             seq1.gate -> adsr1.gate
+             out adsr1.out",
+        );
+        // The first 16th ticks at sample 1378 and plays step 1, so the
+        // attack is under way by 2000, and jumps to the top there.
+        engine
+            .schedule_set(2000, "adsr1", "attack_for", 10)
+            .unwrap();
+        let mut out = vec![0; 2048 * CHANNELS];
+        engine.render(&mut out);
+        assert_eq!(out[2000 * CHANNELS], 32767);
+        assert!(out[2047 * CHANNELS] < 32767);
+    }
+
+    // Renders `patch` with events from `schedule`.
+    fn render_events(patch: &str, schedule: impl FnOnce(&mut Engine)) -> Vec<i16> {
+        let mut engine = engine(patch);
+        schedule(&mut engine);
+        let mut out = vec![0; 8192 * CHANNELS];
+        engine.render(&mut out);
+        out
+    }
+
+    // Events land on their sample, wherever it falls in a block, and also
+    // in components that are stepped together around a feedback loop.
+    #[test]
+    fn events_land_on_their_sample() {
+        let left = |x: &[i16]| x.iter().step_by(CHANNELS).copied().collect::<Vec<i16>>();
+        let first_sound = |x: &[i16]| x.iter().position(|s| *s != 0);
+
+        let out = left(&render_events(
+            "vca1 = Vca
+             vca1.in_cv = 32767
+             vca1.amp_cv = 0
+             out vca1.out",
+            |e| {
+                e.schedule_set(777, "vca1", "amp_cv", 16384).unwrap();
+                e.schedule_set(1500, "vca1", "amp_cv", 0).unwrap();
+            },
+        ));
+        assert_eq!(first_sound(&out), Some(777));
+        assert_eq!(first_sound(&out[778..]), Some(0));
+        assert_eq!(first_sound(&out[1500..]), None);
+        assert_ne!(out[1499], 0);
+
+        let out = left(&render_events(
+            "vca1 = Vca
+             vca1.amp_cv = 0
+             mix1 = Mixer
+             mix1.b = 1000
+             vca1.out -> mix1.a
+             mix1.out -> vca1.in_cv
+             out vca1.out",
+            |e| {
+                e.schedule_set(1030, "vca1", "amp_cv", 16384).unwrap();
+            },
+        ));
+        assert_eq!(first_sound(&out), Some(1030));
+    }
+}
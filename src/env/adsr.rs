@@ -1,5 +1,82 @@
 use crate::util::Component;
-use std::ops::{Index, IndexMut};
+use crate::util::PortInfo;
+use crate::util::PortKind;
+
+const INPUTS: [PortInfo; 7] = [
+    PortInfo {
+        name: "attack_for",
+        kind: PortKind::Cv,
+        unit: "samples",
+        min: 0,
+        max: i16::MAX,
+        default: 0,
+        description: "How long the attack takes to rise from zero to attack_to.",
+    },
+    PortInfo {
+        name: "attack_to",
+        kind: PortKind::Cv,
+        unit: "level",
+        min: 0,
+        max: i16::MAX,
+        default: 0,
+        description: "The peak level reached at the end of the attack.",
+    },
+    PortInfo {
+        name: "decay_for",
+        kind: PortKind::Cv,
+        unit: "samples",
+        min: 0,
+        max: i16::MAX,
+        default: 0,
+        description: "How long the decay takes to fall from attack_to to sustain_at.",
+    },
+    PortInfo {
+        name: "sustain_at",
+        kind: PortKind::Cv,
+        unit: "level",
+        min: 0,
+        max: i16::MAX,
+        default: 0,
+        description: "The level held for as long as the gate stays high.",
+    },
+    PortInfo {
+        name: "release_for",
+        kind: PortKind::Cv,
+        unit: "samples",
+        min: 0,
+        max: i16::MAX,
+        default: 0,
+        description: "How long the release takes to fall from sustain_at to zero.",
+    },
+    PortInfo {
+        name: "trigger",
+        kind: PortKind::Trigger,
+        unit: "",
+        min: i16::MIN,
+        max: i16::MAX,
+        default: 0,
+        description: "Starts the attack on a rising edge.",
+    },
+    PortInfo {
+        name: "gate",
+        kind: PortKind::Gate,
+        unit: "",
+        min: i16::MIN,
+        max: i16::MAX,
+        default: 0,
+        description: "Holds the envelope at sustain_at while high; releases when it goes low.",
+    },
+];
+
+const OUTPUTS: [PortInfo; 1] = [PortInfo {
+    name: "out",
+    kind: PortKind::Cv,
+    unit: "level",
+    min: 0,
+    max: i16::MAX,
+    default: 0,
+    description: "The envelope.",
+}];
 
 #[derive(Copy, Debug, Clone)]
 enum AdsrState {
@@ -21,13 +98,12 @@ pub struct Adsr {
     triggered_at: u32,
     gated_at: u32,
     gate_closed_at: u32,
-    dummy: i16,
     prev_trigger: i16,
     prev_gate: i16,
-    trigger: i16,
+    pub trigger: i16,
     triggered: bool,
     gated: bool,
-    gate: i16,
+    pub gate: i16,
     out: i16,
     state: AdsrState,
     main_counter: u32,
@@ -45,7 +121,6 @@ impl Adsr {
             triggered_at: 0,
             gated_at: 0,
             gate_closed_at: 0,
-            dummy: 0,
             out: 0,
             triggered: false,
             gated: false,
@@ -57,10 +132,26 @@ impl Adsr {
             main_counter: 0,
         }
     }
+
+    pub fn is_off(&self) -> bool {
+        matches!(self.state, AdsrState::Off)
+    }
+
+    // Drops back to silence, so that the next trigger starts a new attack
+    // even if the envelope hadn't finished.
+    pub fn reset(&mut self) {
+        self.state = AdsrState::Off;
+        self.counter = 0;
+        self.out = 0;
+        self.triggered = false;
+        self.gated = false;
+        self.prev_trigger = 0;
+        self.prev_gate = 0;
+    }
 }
 
 impl Component for Adsr {
-    fn tick(&mut self) {}
+    fn tick(&mut self, _velocity: i16) {}
     // The floats make me cry.
     fn step(&mut self) {
         let q = i16::max_value() / 4;
@@ -82,40 +173,45 @@ impl Component for Adsr {
             (_, true, _) => (),
 
             (AdsrState::Attack, false, true) => {
-                self.out = ((self.attack_to as f64) * (self.counter as f64)
-                    / (self.attack_for as f64)) as i16;
-                if self.counter == self.attack_for {
+                // The stage lengths can be changed part way through a
+                // stage, so the counter may already be past the end.
+                let counter = self.counter.min(self.attack_for);
+                self.out =
+                    ((self.attack_to as f64) * (counter as f64) / (self.attack_for as f64)) as i16;
+                if self.counter >= self.attack_for {
                     self.state = AdsrState::Decay;
                     self.counter = 0;
                     self.main_counter = 0;
                 } else {
-                    self.counter = self.counter.checked_add(1).unwrap();
+                    self.counter += 1;
                 }
             }
             (AdsrState::Decay, false, true) => {
+                let counter = self.counter.min(self.decay_for);
                 self.out = (((self.attack_to as f64) - (self.sustain_at as f64))
-                    * ((self.decay_for as f64) - (self.counter as f64))
+                    * ((self.decay_for as f64) - (counter as f64))
                     / (self.decay_for as f64)) as i16
                     + self.sustain_at;
-                if self.counter == self.decay_for {
+                if self.counter >= self.decay_for {
                     self.state = AdsrState::Sustain;
                     self.counter = 0;
                     self.main_counter = 0;
                 } else {
-                    self.counter = self.counter.checked_add(1).unwrap();
+                    self.counter += 1;
                 }
             }
             (AdsrState::Sustain, false, true) => self.out = self.sustain_at,
             (AdsrState::Release, false, false) => {
+                let counter = self.counter.min(self.release_for);
                 self.out = ((self.sustain_at as f64)
-                    * ((self.release_for as f64) - (self.counter as f64))
+                    * ((self.release_for as f64) - (counter as f64))
                     / (self.release_for as f64)) as i16;
-                if self.counter == self.release_for {
+                if self.counter >= self.release_for {
                     self.state = AdsrState::Off;
                     self.counter = 0;
                     self.main_counter = 0;
                 } else {
-                    self.counter = self.counter.checked_add(1).unwrap();
+                    self.counter += 1;
                 }
             }
             // Not sure what to do here? Gated without a trigger?
@@ -132,50 +228,31 @@ impl Component for Adsr {
         };
         self.triggered = false;
     }
-    fn inputs(&self) -> Vec<&'static str> {
-        vec![
-            "attack_for",
-            "attack_to",
-            "decay_for",
-            "sustain_at",
-            "release_for",
-            "trigger",
-            "gate",
-        ]
+    fn input_info(&self) -> &'static [PortInfo] {
+        &INPUTS
     }
 
-    fn outputs(&self) -> Vec<&'static str> {
-        vec!["out"]
+    fn output_info(&self) -> &'static [PortInfo] {
+        &OUTPUTS
     }
-}
 
-impl Index<&str> for Adsr {
-    type Output = i16;
-
-    fn index(&self, i: &str) -> &Self::Output {
-        match i {
-            "out" => &self.out,
-            "attack_for" => &self.attack_for,
-            "attack_to" => &self.attack_to,
-            "decay_for" => &self.decay_for,
-            "sustain_at" => &self.sustain_at,
-            "release_for" => &self.release_for,
-            _ => &0,
+    fn input_mut(&mut self, slot: usize) -> &mut i16 {
+        match slot {
+            0 => &mut self.attack_for,
+            1 => &mut self.attack_to,
+            2 => &mut self.decay_for,
+            3 => &mut self.sustain_at,
+            4 => &mut self.release_for,
+            5 => &mut self.trigger,
+            6 => &mut self.gate,
+            _ => panic!("Adsr has no input slot {}", slot),
         }
     }
-}
 
-impl IndexMut<&str> for Adsr {
-    fn index_mut(&mut self, i: &str) -> &mut Self::Output {
-        match i {
-            "attack_for" => &mut self.attack_for,
-            "attack_to" => &mut self.attack_to,
-            "decay_for" => &mut self.decay_for,
-            "sustain_at" => &mut self.sustain_at,
-            "release_for" => &mut self.release_for,
-            "trigger" => &mut self.trigger,
-            "gate" => &mut self.gate,
-            _ => &mut self.dummy,
+    fn output(&self, slot: usize) -> i16 {
+        match slot {
+            0 => self.out,
+            _ => panic!("Adsr has no output slot {}", slot),
         }
     }
 }
@@ -0,0 +1,85 @@
+use crate::audio::AvailableComponents;
+
+// The most events that can be waiting for their sample.
+pub const MAX_EVENTS: usize = 1024;
+
+// A change to a component, due at a sample counted from the start of the
+// engine. It's delivered just before that sample is rendered, even part way
+// through a block.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Event {
+    pub at: u64,
+    // The index of the component in the engine.
+    pub target: usize,
+    pub action: Action,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    // Sets an input, as with a value in a patch file.
+    Set { slot: usize, value: i16 },
+    // Notes for a Voices component.
+    NoteOn(i16),
+    NoteOff(i16),
+}
+
+impl Action {
+    pub fn apply(&self, c: &mut AvailableComponents) {
+        match (self, c) {
+            (Action::Set { slot, value }, c) => *c.as_component_mut().input_mut(*slot) = *value,
+            (Action::NoteOn(note), AvailableComponents::Voices(voices)) => voices.note_on(*note),
+            (Action::NoteOff(note), AvailableComponents::Voices(voices)) => voices.note_off(*note),
+            _ => (),
+        }
+    }
+}
+
+// Events waiting for their sample, in the order they're due; events due at
+// the same sample keep the order they were scheduled in. The space for them
+// is allocated up front, so that the audio thread can schedule them.
+pub struct Events(Vec<Event>);
+
+impl Events {
+    pub fn new() -> Events {
+        Events(Vec::with_capacity(MAX_EVENTS))
+    }
+
+    // How many more events there's room for.
+    pub fn room(&self) -> usize {
+        self.0.capacity() - self.0.len()
+    }
+
+    // Returns the event back if there's no room for it.
+    pub fn push(&mut self, e: Event) -> Result<(), Event> {
+        if self.0.len() == self.0.capacity() {
+            return Err(e);
+        }
+        let i = self.0.partition_point(|x| x.at <= e.at);
+        self.0.insert(i, e);
+        Ok(())
+    }
+
+    // The events due before sample `before`, including any that are late.
+    pub fn due(&self, before: u64) -> &[Event] {
+        &self.0[..self.0.partition_point(|x| x.at < before)]
+    }
+
+    pub fn remove_due(&mut self, before: u64) {
+        let n = self.due(before).len();
+        self.0.drain(..n);
+    }
+
+    // Follows the components to their new indices when a new graph is
+    // swapped in, as listed in an Update's `moves`. Events for components
+    // that were removed are dropped.
+    pub fn retarget(&mut self, moves: &[(usize, usize)]) {
+        self.0
+            .retain_mut(|e| match moves.iter().find(|(from, _)| *from == e.target) {
+                Some((_, to)) => {
+                    e.target = *to;
+                    true
+                }
+                None => false,
+            });
+    }
+}
@@ -0,0 +1,411 @@
+// Regression tests for the DSP. Each test renders a small patch for a
+// fixed number of samples and compares it with a reference render in
+// tests/golden, so that a change to how a component sounds shows up as a
+// failing test, with where and by how much the output moved.
+//
+// After a change that is meant to alter the sound, listen to the new
+// renders written to target/golden and then update the references with
+//
+//     GOLDEN_BLESS=1 cargo test golden
+//
+// Noise is only repeatable with a seed, e.g. `table=white_noise seed=1`.
+
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::path::PathBuf;
+
+use wav::bit_depth::BitDepth;
+
+use crate::engine::Engine;
+use crate::engine::CHANNELS;
+use crate::patch::Patch;
+use crate::render;
+use crate::util::DEFAULT_RATE;
+
+// Fast enough that a render holds a few 16ths: one is 1378 samples.
+const TEMPO: u64 = 480;
+const LEN: usize = 8192;
+
+// Where, and by how much, a render differs from its reference.
+#[derive(Debug, PartialEq)]
+struct Diff {
+    // The index of the first sample out of tolerance, in the interleaved
+    // frames, and the expected and rendered values there.
+    first: Option<(usize, i16, i16)>,
+    // How many samples are out of tolerance.
+    count: usize,
+    max: i32,
+    rms: f64,
+    lens: (usize, usize),
+}
+
+impl Diff {
+    fn new(expected: &[i16], actual: &[i16], tolerance: i32) -> Diff {
+        let mut first = None;
+        let mut count = 0;
+        let mut max = 0;
+        let mut sum = 0.;
+        for (i, (e, a)) in expected.iter().zip(actual).enumerate() {
+            let d = (*a as i32 - *e as i32).abs();
+            max = max.max(d);
+            sum += (d as f64) * (d as f64);
+            if d > tolerance {
+                count += 1;
+                first = first.or(Some((i, *e, *a)));
+            }
+        }
+        let n = expected.len().min(actual.len()).max(1);
+        Diff {
+            first,
+            count,
+            max,
+            rms: (sum / n as f64).sqrt(),
+            lens: (expected.len(), actual.len()),
+        }
+    }
+
+    fn ok(&self) -> bool {
+        self.first.is_none() && self.lens.0 == self.lens.1
+    }
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.lens.0 != self.lens.1 {
+            writeln!(
+                f,
+                "expected {} samples, rendered {}",
+                self.lens.0, self.lens.1
+            )?;
+        }
+        if let Some((i, e, a)) = self.first {
+            writeln!(
+                f,
+                "first divergent sample: {} of channel {} (expected {}, rendered {})",
+                i / CHANNELS,
+                i % CHANNELS,
+                e,
+                a
+            )?;
+        }
+        write!(
+            f,
+            "{} samples out of tolerance, max error {}, RMS error {:.2}",
+            self.count, self.max, self.rms
+        )
+    }
+}
+
+fn path(dir: &str, name: &str) -> PathBuf {
+    [env!("CARGO_MANIFEST_DIR"), dir, &format!("{}.wav", name)]
+        .iter()
+        .collect()
+}
+
+fn read_wav(path: &PathBuf) -> Option<Vec<i16>> {
+    let (_, data) = wav::read(&mut File::open(path).ok()?).ok()?;
+    match data {
+        BitDepth::Sixteen(x) => Some(x),
+        _ => None,
+    }
+}
+
+// Renders `patch` and compares it with tests/golden/`name`.wav, allowing
+// each sample to be off by `tolerance`.
+fn check(name: &str, patch: &str, tolerance: i32) {
+    check_at(name, patch, DEFAULT_RATE, tolerance)
+}
+
+fn check_at(name: &str, patch: &str, rate: u32, tolerance: i32) {
+    check_events(name, patch, rate, |_| (), tolerance)
+}
+
+// Renders `patch` with events from `schedule`.
+fn render_events(
+    name: &str,
+    patch: &str,
+    rate: u32,
+    schedule: impl FnOnce(&mut Engine),
+) -> Vec<i16> {
+    let patch = Patch::parse(patch, rate).unwrap_or_else(|e| panic!("{}: {}", name, e));
+    let mut engine = Engine::new(patch, TEMPO);
+    schedule(&mut engine);
+    let mut actual = vec![0; LEN * CHANNELS];
+    engine.render(&mut actual);
+    actual
+}
+
+fn check_events(
+    name: &str,
+    patch: &str,
+    rate: u32,
+    schedule: impl FnOnce(&mut Engine),
+    tolerance: i32,
+) {
+    let actual = render_events(name, patch, rate, schedule);
+
+    let golden = path("tests/golden", name);
+    if std::env::var_os("GOLDEN_BLESS").is_some() {
+        fs::create_dir_all(golden.parent().unwrap()).unwrap();
+        render::write_wav(&golden, rate, actual).unwrap();
+        return;
+    }
+
+    let expected = match read_wav(&golden) {
+        Some(x) => x,
+        None => panic!(
+            "{}: no reference at {} (run with GOLDEN_BLESS=1 to write it)",
+            name,
+            golden.display()
+        ),
+    };
+    let diff = Diff::new(&expected, &actual, tolerance);
+    if !diff.ok() {
+        let rendered = path("target/golden", name);
+        fs::create_dir_all(rendered.parent().unwrap()).unwrap();
+        render::write_wav(&rendered, rate, actual).unwrap();
+        panic!(
+            "{} differs from {}:\n{}\nthe new render is in {}",
+            name,
+            golden.display(),
+            diff,
+            rendered.display()
+        );
+    }
+}
+
+#[test]
+fn diff_reports_first_divergent_sample() {
+    let diff = Diff::new(&[0, 10, 20, 30], &[0, 12, 25, 30], 2);
+    assert_eq!(diff.first, Some((2, 20, 25)));
+    assert_eq!(diff.count, 1);
+    assert_eq!(diff.max, 5);
+    assert!((diff.rms - (29f64 / 4.).sqrt()).abs() < 1e-9);
+    assert!(!diff.ok());
+    assert!(Diff::new(&[0, 10], &[1, 9], 1).ok());
+    assert!(!Diff::new(&[0, 10], &[0], 1).ok());
+}
+
+#[test]
+fn golden_sin() {
+    check(
+        "sin",
+        "wto1 = WaveTableOsc table=sin
+         wto1.freq = 69
+         out wto1.out",
+        0,
+    );
+}
+
+// The same note and arpeggio at other rates should sound the same, just
+// with more samples.
+#[test]
+fn golden_rates() {
+    for rate in [48_000, 96_000] {
+        check_at(
+            &format!("arp_{}", rate),
+            "seq1 = BasicSeq beats=0,1,2,3 beat_len=1
+             arp1 = BasicArp scale=C octave=4
+             wto1 = WaveTableOsc table=sin
+             seq1.trigger -> arp1.trigger_in
+             arp1.note_cv_out -> wto1.freq
+             out wto1.out",
+            rate,
+            0,
+        );
+    }
+}
+
+#[test]
+fn golden_pan() {
+    check(
+        "pan",
+        "wto1 = WaveTableOsc table=saw
+         wto1.freq = 60
+         lfo1 = WaveTableOsc table=triangle
+         lfo1.freq = 12
+         pan1 = Pan
+         wto1.out -> pan1.in_cv
+         lfo1.out -> pan1.pan
+         out pan1.left pan1.right",
+        0,
+    );
+}
+
+#[test]
+fn golden_fm() {
+    check(
+        "fm",
+        "wto1 = WaveTableOsc table=sin
+         wto1.freq = 69
+         wto1.modulation_idx = 16383
+         wto2 = WaveTableOsc table=triangle
+         wto2.freq = 45
+         wto2.out -> wto1.modulation
+         out wto1.out",
+        0,
+    );
+}
+
+#[test]
+fn golden_noise() {
+    check(
+        "noise",
+        "wto1 = WaveTableOsc table=white_noise seed=1
+         out wto1.out",
+        0,
+    );
+}
+
+#[test]
+fn golden_mixer() {
+    check(
+        "mixer",
+        "wto1 = WaveTableOsc table=saw
+         wto1.freq = 57
+         wto2 = WaveTableOsc table=square
+         wto2.freq = 64
+         vca1 = Vca
+         vca1.amp_cv = 8191
+         vca2 = Vca
+         vca2.amp_cv = 4095
+         mix1 = Mixer
+         wto1.out -> vca1.in_cv
+         wto2.out -> vca2.in_cv
+         vca1.out -> mix1.a
+         vca2.out -> mix1.b
+         out mix1.out",
+        0,
+    );
+}
+
+#[test]
+fn golden_adsr() {
+    check(
+        "adsr",
+        "seq1 = BasicSeq beats=0,1,3 beat_len=1
+         adsr1 = Adsr
+         adsr1.attack_for = 256
+         adsr1.attack_to = 32767
+         adsr1.decay_for = 256
+         adsr1.sustain_at = 16383
+         adsr1.release_for = 512
+         seq1.trigger -> adsr1.trigger
+         seq1.gate -> adsr1.gate
+         out adsr1.out",
+        0,
+    );
+}
+
+#[test]
+fn golden_arp() {
+    check(
+        "arp",
+        "seq1 = BasicSeq beats=0,1,2,3,4,5 beat_len=1
+         arp1 = BasicArp scale=C octave=4
+         wto1 = WaveTableOsc table=saw
+         adsr1 = Adsr
+         adsr1.attack_for = 128
+         adsr1.attack_to = 16383
+         adsr1.decay_for = 256
+         adsr1.sustain_at = 8191
+         adsr1.release_for = 256
+         vca1 = Vca
+         seq1.trigger -> arp1.trigger_in
+         seq1.trigger -> adsr1.trigger
+         seq1.gate -> adsr1.gate
+         arp1.note_cv_out -> wto1.freq
+         wto1.out -> vca1.in_cv
+         adsr1.out -> vca1.amp_cv
+         out vca1.out",
+        0,
+    );
+}
+
+#[test]
+fn golden_reverb() {
+    check(
+        "reverb",
+        "seq1 = BasicSeq beats=1 beat_len=1
+         wto1 = WaveTableOsc table=square
+         wto1.freq = 60
+         adsr1 = Adsr
+         adsr1.attack_for = 64
+         adsr1.attack_to = 16383
+         adsr1.decay_for = 128
+         adsr1.sustain_at = 8191
+         adsr1.release_for = 256
+         vca1 = Vca
+         rvb1 = BasicReverb delay=34,20,11
+         seq1.trigger -> adsr1.trigger
+         seq1.gate -> adsr1.gate
+         wto1.out -> vca1.in_cv
+         adsr1.out -> vca1.amp_cv
+         vca1.out -> rvb1.cv_in
+         out rvb1.out",
+        0,
+    );
+}
+
+#[test]
+fn golden_voices() {
+    check(
+        "voices",
+        "seq1 = BasicSeq beats=0,1,2,3,4,5 beat_len=2
+         arp1 = BasicArp scale=A octave=3
+         poly1 = Voices voices=2 steal=oldest table=triangle
+         poly1.release_for = 2048
+         seq1.trigger -> arp1.trigger_in
+         seq1.trigger -> poly1.trigger
+         seq1.gate -> poly1.gate
+         arp1.note_cv_out -> poly1.note
+         out poly1.out",
+        0,
+    );
+}
+
+// Another golden render stands in for an input device.
+#[test]
+fn golden_audio_in() {
+    check(
+        "audio_in",
+        &format!(
+            "in1 = AudioIn file={}
+             vca1 = Vca
+             vca1.amp_cv = 16383
+             in1.out -> vca1.in_cv
+             out vca1.out",
+            path("tests/golden", "sin").display()
+        ),
+        0,
+    );
+}
+
+// Drum hits on samples that don't line up with blocks or 16ths.
+#[test]
+fn golden_events() {
+    check_events(
+        "events",
+        "wto1 = WaveTableOsc table=white_noise seed=3
+         adsr1 = Adsr
+         adsr1.attack_for = 16
+         adsr1.attack_to = 32767
+         adsr1.decay_for = 512
+         adsr1.sustain_at = 0
+         adsr1.release_for = 64
+         vca1 = Vca
+         wto1.out -> vca1.in_cv
+         adsr1.out -> vca1.amp_cv
+         out vca1.out",
+        DEFAULT_RATE,
+        |e| {
+            for at in [100, 1337, 2049, 5000, 5003] {
+                e.schedule_set(at, "adsr1", "gate", i16::MAX).unwrap();
+                e.schedule_set(at, "adsr1", "trigger", i16::MAX).unwrap();
+                e.schedule_set(at + 600, "adsr1", "gate", 0).unwrap();
+            }
+        },
+        0,
+    );
+}
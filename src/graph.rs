@@ -0,0 +1,277 @@
+use std::ops::Range;
+
+// A wire with its ends resolved to component indices and port slots.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Wire {
+    pub src: usize,
+    pub out_slot: usize,
+    pub dst: usize,
+    pub in_slot: usize,
+    // Set by Schedule when the wire feeds back into a component that has
+    // already been stepped, and so arrives one sample late.
+    pub delayed: bool,
+}
+
+impl Wire {
+    pub fn new(src: usize, out_slot: usize, dst: usize, in_slot: usize) -> Wire {
+        Wire {
+            src,
+            out_slot,
+            dst,
+            in_slot,
+            delayed: false,
+        }
+    }
+}
+
+// The order in which to step the components so that a signal makes it
+// through a whole chain of wires (e.g. seq1 -> adsr1 -> vca1 -> rvb1) in
+// a single sample.
+#[derive(Debug, PartialEq)]
+pub struct Schedule {
+    // Indices of the components, in the order to step them, along with
+    // the range of `wires` going into each one.
+    pub order: Vec<(usize, Range<usize>)>,
+    // The wires, grouped by the position of their destination in `order`.
+    pub wires: Vec<Wire>,
+    // Indices (into the wires given to `new`) of the delayed wires.
+    pub delayed: Vec<usize>,
+    // Ranges of positions in `order` that a delayed wire loops back over.
+    // These have to be stepped a sample at a time, all together, rather
+    // than a block at a time, one component after the other.
+    pub feedback: Vec<Range<usize>>,
+}
+
+impl Schedule {
+    // Components that don't depend on each other keep their declaration
+    // order. When every component left waits on another, one of them is
+    // in a cycle that nothing else left feeds: its strongly connected
+    // components are found, and the cycle is broken at the earliest
+    // declared component of the first one that isn't fed from outside.
+    // The wires into it from the rest of the cycle become one-sample
+    // delays; components that are only downstream of a cycle wait for it
+    // as usual.
+    pub fn new(components: usize, wires: &[Wire]) -> Schedule {
+        let mut in_degree = vec![0; components];
+        for wire in wires.iter() {
+            in_degree[wire.dst] += 1;
+        }
+
+        let mut placed = vec![false; components];
+        let mut order = Vec::with_capacity(components);
+        let mut delayed = vec![];
+        while order.len() < components {
+            let next = (0..components).find(|i| !placed[*i] && in_degree[*i] == 0);
+            let next = match next {
+                Some(i) => i,
+                None => {
+                    let live = |w: usize| {
+                        let wire = &wires[w];
+                        !placed[wire.src] && !placed[wire.dst] && !delayed.contains(&w)
+                    };
+                    let scc = strongly_connected(wires, &placed, live);
+                    // Whether a wire comes into `i` from outside its SCC.
+                    let fed = |i: usize| {
+                        (0..wires.len())
+                            .any(|w| live(w) && wires[w].dst == i && scc[wires[w].src] != scc[i])
+                    };
+                    let i = (0..components)
+                        .find(|i| {
+                            !placed[*i] && !(0..components).any(|j| scc[j] == scc[*i] && fed(j))
+                        })
+                        .unwrap();
+                    for (w, wire) in wires.iter().enumerate() {
+                        if wire.dst == i && !placed[wire.src] && !delayed.contains(&w) {
+                            delayed.push(w);
+                        }
+                    }
+                    in_degree[i] = 0;
+                    i
+                }
+            };
+
+            placed[next] = true;
+            order.push(next);
+            for (w, wire) in wires.iter().enumerate() {
+                if wire.src == next && !placed[wire.dst] && !delayed.contains(&w) {
+                    in_degree[wire.dst] -= 1;
+                }
+            }
+        }
+        delayed.sort_unstable();
+
+        let mut position = vec![0; components];
+        for (p, i) in order.iter().enumerate() {
+            position[*i] = p;
+        }
+        let mut feedback: Vec<Range<usize>> = vec![];
+        for w in delayed.iter() {
+            let wire = &wires[*w];
+            feedback.push(position[wire.dst]..position[wire.src] + 1);
+        }
+        feedback.sort_unstable_by_key(|r| r.start);
+        let feedback = feedback
+            .into_iter()
+            .fold(vec![], |mut merged: Vec<Range<usize>>, r| {
+                match merged.last_mut() {
+                    Some(last) if r.start < last.end => last.end = last.end.max(r.end),
+                    _ => merged.push(r),
+                }
+                merged
+            });
+
+        let mut table = Vec::with_capacity(wires.len());
+        let order = order
+            .into_iter()
+            .map(|i| {
+                let start = table.len();
+                for (w, wire) in wires.iter().enumerate() {
+                    if wire.dst == i {
+                        table.push(Wire {
+                            delayed: delayed.contains(&w),
+                            ..*wire
+                        });
+                    }
+                }
+                (i, start..table.len())
+            })
+            .collect();
+
+        Schedule {
+            order,
+            wires: table,
+            delayed,
+            feedback,
+        }
+    }
+}
+
+// Tarjan's algorithm over the components not yet placed and the wires
+// `live` keeps: the index of the strongly connected component each one
+// belongs to.
+fn strongly_connected(wires: &[Wire], placed: &[bool], live: impl Fn(usize) -> bool) -> Vec<usize> {
+    struct Tarjan<'a, F: Fn(usize) -> bool> {
+        wires: &'a [Wire],
+        live: F,
+        index: Vec<Option<usize>>,
+        low: Vec<usize>,
+        stack: Vec<usize>,
+        on_stack: Vec<bool>,
+        scc: Vec<usize>,
+        next_index: usize,
+        next_scc: usize,
+    }
+
+    impl<F: Fn(usize) -> bool> Tarjan<'_, F> {
+        fn visit(&mut self, i: usize) {
+            self.index[i] = Some(self.next_index);
+            self.low[i] = self.next_index;
+            self.next_index += 1;
+            self.stack.push(i);
+            self.on_stack[i] = true;
+            for w in 0..self.wires.len() {
+                let wire = self.wires[w];
+                if wire.src != i || !(self.live)(w) {
+                    continue;
+                }
+                match self.index[wire.dst] {
+                    None => {
+                        self.visit(wire.dst);
+                        self.low[i] = self.low[i].min(self.low[wire.dst]);
+                    }
+                    Some(index) if self.on_stack[wire.dst] => {
+                        self.low[i] = self.low[i].min(index);
+                    }
+                    Some(_) => (),
+                }
+            }
+            if Some(self.low[i]) == self.index[i] {
+                loop {
+                    let j = self.stack.pop().unwrap();
+                    self.on_stack[j] = false;
+                    self.scc[j] = self.next_scc;
+                    if j == i {
+                        break;
+                    }
+                }
+                self.next_scc += 1;
+            }
+        }
+    }
+
+    let mut tarjan = Tarjan {
+        wires,
+        live,
+        index: vec![None; placed.len()],
+        low: vec![0; placed.len()],
+        stack: vec![],
+        on_stack: vec![false; placed.len()],
+        scc: vec![usize::MAX; placed.len()],
+        next_index: 0,
+        next_scc: 0,
+    };
+    for (i, placed) in placed.iter().enumerate() {
+        if !placed && tarjan.index[i].is_none() {
+            tarjan.visit(i);
+        }
+    }
+    tarjan.scc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(components: usize, wires: &[(usize, usize)]) -> Schedule {
+        let wires = wires
+            .iter()
+            .map(|(src, dst)| Wire::new(*src, 0, *dst, 0))
+            .collect::<Vec<Wire>>();
+        Schedule::new(components, &wires)
+    }
+
+    fn order(schedule: &Schedule) -> Vec<usize> {
+        schedule.order.iter().map(|x| x.0).collect()
+    }
+
+    #[test]
+    fn steps_each_component_after_what_feeds_it() {
+        // 3 -> 1 -> 0, and 2 on its own.
+        let s = schedule(4, &[(1, 0), (3, 1)]);
+        assert_eq!(order(&s), [2, 3, 1, 0]);
+        assert!(s.delayed.is_empty());
+        assert!(s.feedback.is_empty());
+        // The wires follow their destinations.
+        let dsts = s.wires.iter().map(|w| w.dst).collect::<Vec<usize>>();
+        assert_eq!(dsts, [1, 0]);
+        assert_eq!(s.order[2], (1, 0..1));
+    }
+
+    #[test]
+    fn delays_a_self_loop() {
+        let s = schedule(2, &[(0, 0), (0, 1)]);
+        assert_eq!(order(&s), [0, 1]);
+        assert_eq!(s.delayed, [0]);
+        assert_eq!(s.feedback, vec![0..1]);
+        assert!(s.wires[0].delayed);
+        assert!(!s.wires[1].delayed);
+    }
+
+    #[test]
+    fn breaks_a_cycle_at_its_earliest_component() {
+        let s = schedule(2, &[(0, 1), (1, 0)]);
+        assert_eq!(order(&s), [0, 1]);
+        assert_eq!(s.delayed, [1]);
+        assert_eq!(s.feedback, vec![0..2]);
+    }
+
+    #[test]
+    fn leaves_what_is_downstream_of_a_cycle_alone() {
+        // 2 -> 0 is only fed by the cycle 1 <-> 2, so it isn't delayed,
+        // even though 0 is declared first.
+        let s = schedule(3, &[(2, 0), (1, 2), (2, 1)]);
+        assert_eq!(order(&s), [1, 2, 0]);
+        assert_eq!(s.delayed, [2]);
+        assert_eq!(s.feedback, vec![0..2]);
+    }
+}
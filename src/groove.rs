@@ -0,0 +1,81 @@
+use std::fs;
+use std::path::Path;
+
+// How far a step can be moved from the grid, as a percentage of a 16th.
+pub const MAX_TIMING: f64 = 25.;
+
+// How late and how loud each 16th of a bar is, in a loop of up to 16
+// steps. A groove file has a line per step, each with the step's timing,
+// as a percentage of a 16th (negative is early), and its velocity offset,
+// as a percentage of full scale (0 is full scale, -100 silent). `#` starts
+// a comment.
+//
+//     # step 2 late and soft, step 4 early
+//     0   0
+//     10 -20
+//     0   0
+//     -5  0
+#[derive(Debug, Clone, PartialEq)]
+pub struct Groove {
+    // Fractions of a 16th, and of full scale.
+    steps: Vec<(f64, f64)>,
+}
+
+impl Groove {
+    // Every step on the grid, at full scale.
+    pub fn straight() -> Groove {
+        Groove {
+            steps: vec![(0., 0.)],
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Groove, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        Groove::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn parse(text: &str) -> Result<Groove, String> {
+        let mut steps = vec![];
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let err = |msg: &str| format!("line {}: {}", n + 1, msg);
+            let values = line
+                .split_whitespace()
+                .map(|x| x.parse::<f64>())
+                .collect::<Result<Vec<f64>, _>>()
+                .map_err(|_| err("expected a timing and a velocity, as numbers"))?;
+            let (timing, velocity) = match values[..] {
+                [timing, velocity] => (timing, velocity),
+                _ => return Err(err("expected a timing and a velocity")),
+            };
+            if !(-MAX_TIMING..=MAX_TIMING).contains(&timing) {
+                return Err(err(&format!(
+                    "the timing must be within {}% of the grid",
+                    MAX_TIMING
+                )));
+            }
+            if !(-100. ..=0.).contains(&velocity) {
+                return Err(err("the velocity offset must be from -100 to 0"));
+            }
+            steps.push((timing / 100., velocity / 100.));
+        }
+        if steps.is_empty() || steps.len() > 16 {
+            return Err("a groove has from 1 to 16 steps".to_string());
+        }
+        Ok(Groove { steps })
+    }
+
+    // How late `step` comes, as a fraction of a 16th.
+    pub fn timing(&self, step: u64) -> f64 {
+        self.steps[(step % self.steps.len() as u64) as usize].0
+    }
+
+    // How loud `step` is, from 0 to full scale.
+    pub fn velocity(&self, step: u64) -> i16 {
+        let v = self.steps[(step % self.steps.len() as u64) as usize].1;
+        (i16::MAX as f64 * (1. + v)).round() as i16
+    }
+}
@@ -0,0 +1,186 @@
+use std::sync::Mutex;
+
+use rtrb::Consumer;
+
+use crate::engine::MAX_BLOCK;
+use crate::util::Component;
+use crate::util::PortInfo;
+use crate::util::PortKind;
+
+const INPUTS: [PortInfo; 1] = [PortInfo {
+    name: "level",
+    kind: PortKind::Cv,
+    unit: "level",
+    min: 0,
+    max: i16::MAX,
+    default: i16::MAX,
+    description: "How much of the input to pass on.",
+}];
+
+const OUTPUTS: [PortInfo; 1] = [PortInfo {
+    name: "out",
+    kind: PortKind::Audio,
+    unit: "",
+    min: i16::MIN,
+    max: i16::MAX,
+    default: 0,
+    description: "The samples from the input device or file, or silence when none have arrived.",
+}];
+
+// Where an AudioIn's samples come from.
+pub enum Source {
+    // A ring buffer filled by an input device's own thread, so that the
+    // audio thread never waits on it.
+    Device(Consumer<i16>),
+    // The samples of a file, played over and over.
+    File(Vec<i16>),
+}
+
+enum Feed {
+    // Only ever reached through `get_mut`, which doesn't lock: the Mutex is
+    // there because components have to be Sync and a Consumer isn't.
+    Device(Mutex<Consumer<i16>>),
+    File { samples: Vec<i16>, pos: usize },
+}
+
+// Brings outside audio into the patch, from an input device or a file.
+pub struct AudioIn {
+    source: Option<Feed>,
+    pub level: i16,
+    pub out: i16,
+    // How many samples were missing because the source had fallen behind.
+    pub underruns: u64,
+}
+
+impl AudioIn {
+    // With no source, the output is silent.
+    pub fn new(source: Option<Source>) -> AudioIn {
+        AudioIn {
+            source: source.map(|x| match x {
+                Source::Device(rx) => Feed::Device(Mutex::new(rx)),
+                Source::File(samples) => Feed::File { samples, pos: 0 },
+            }),
+            level: i16::MAX,
+            out: 0,
+            underruns: 0,
+        }
+    }
+}
+
+impl Component for AudioIn {
+    fn tick(&mut self, _velocity: i16) {}
+    fn step(&mut self) {
+        let s = match &mut self.source {
+            Some(Feed::Device(rx)) => {
+                let rx = rx.get_mut().unwrap();
+                // A backlog, such as the one queued while the patch was
+                // being built, would never be caught up on, so all but the
+                // newest block of it is skipped rather than kept as
+                // latency.
+                let backlog = rx.slots();
+                if backlog > 2 * MAX_BLOCK {
+                    rx.read_chunk(backlog - MAX_BLOCK).unwrap().commit_all();
+                }
+                match rx.pop() {
+                    Ok(s) => s,
+                    Err(_) => {
+                        self.underruns += 1;
+                        0
+                    }
+                }
+            }
+            Some(Feed::File { samples, pos }) => {
+                let s = samples[*pos];
+                *pos = (*pos + 1) % samples.len();
+                s
+            }
+            None => return,
+        };
+        self.out = (((self.level as i32) * (s as i32)) >> 15) as i16;
+    }
+
+    fn input_info(&self) -> &'static [PortInfo] {
+        &INPUTS
+    }
+
+    fn output_info(&self) -> &'static [PortInfo] {
+        &OUTPUTS
+    }
+
+    fn input_mut(&mut self, slot: usize) -> &mut i16 {
+        match slot {
+            0 => &mut self.level,
+            _ => panic!("AudioIn has no input slot {}", slot),
+        }
+    }
+
+    fn output(&self, slot: usize) -> i16 {
+        match slot {
+            0 => self.out,
+            _ => panic!("AudioIn has no output slot {}", slot),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rtrb::RingBuffer;
+
+    use super::*;
+    use crate::audio::AvailableComponents;
+    use crate::engine::Engine;
+    use crate::engine::CHANNELS;
+    use crate::input;
+    use crate::patch::Patch;
+    use crate::util::DEFAULT_RATE;
+
+    // A file plays the same however fast it's rendered, and for longer
+    // than a device's ring would hold, looping back to its start.
+    #[test]
+    fn files_loop_without_underruns() {
+        let path = [env!("CARGO_MANIFEST_DIR"), "tests", "golden", "sin.wav"]
+            .iter()
+            .collect::<std::path::PathBuf>();
+        let samples = input::load_file(&path, DEFAULT_RATE).unwrap();
+        let patch = format!("in1 = AudioIn file={}\nout in1.out", path.display());
+        let mut engine = Engine::new(Patch::parse(&patch, DEFAULT_RATE).unwrap(), 120);
+
+        let len = 3 * DEFAULT_RATE as usize;
+        assert!(len > 2 * samples.len());
+        let mut out = vec![0; len * CHANNELS];
+        engine.render(&mut out);
+        for (n, frame) in out.chunks(CHANNELS).enumerate() {
+            let s = samples[n % samples.len()];
+            assert_eq!(
+                frame[0],
+                ((i16::MAX as i32 * s as i32) >> 15) as i16,
+                "at {}",
+                n
+            );
+        }
+        match engine.component("in1") {
+            Some(AvailableComponents::AudioIn(in1)) => assert_eq!(in1.underruns, 0),
+            _ => unreachable!(),
+        }
+    }
+
+    // What a device recorded before the patch started is skipped, bar the
+    // newest block, so it doesn't delay everything after it.
+    #[test]
+    fn device_backlogs_are_skipped() {
+        let (mut tx, rx) = RingBuffer::new(4 * MAX_BLOCK);
+        for n in 0..4 * MAX_BLOCK {
+            tx.push(n as i16).unwrap();
+        }
+        let mut in1 = AudioIn::new(Some(Source::Device(rx)));
+        in1.step();
+        let s = 3 * MAX_BLOCK as i32;
+        assert_eq!(in1.out, ((i16::MAX as i32 * s) >> 15) as i16);
+        for _ in 1..MAX_BLOCK {
+            in1.step();
+        }
+        assert_eq!(in1.underruns, 0);
+        in1.step();
+        assert_eq!(in1.underruns, 1);
+    }
+}
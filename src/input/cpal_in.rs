@@ -0,0 +1,143 @@
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+use cpal::traits::DeviceTrait;
+use cpal::traits::HostTrait;
+use cpal::traits::StreamTrait;
+use cpal::Sample;
+use cpal::SampleFormat;
+use cpal::SampleRate;
+use rtrb::Consumer;
+use rtrb::Producer;
+use rtrb::RingBuffer;
+
+use crate::engine::MAX_BLOCK;
+use crate::out;
+
+// The host that input devices are opened on, from --host, which is the
+// output's host too. The default host until it's set.
+static HOST: OnceLock<String> = OnceLock::new();
+
+pub fn set_host(name: Option<String>) {
+    if let Some(name) = name {
+        let _ = HOST.set(name);
+    }
+}
+
+// Records from an input device, by name or index, or the default one, at
+// `rate`. The channels are mixed down to one.
+//
+// The stream is built and kept on a thread of its own, as it can't always
+// be moved between threads, and is closed once the returned Consumer has
+// been dropped.
+//
+// The ring only holds a few blocks: anything more queued up would only be
+// latency, and AudioIn skips all but the newest block of it anyway.
+pub fn open(device: Option<&str>, rate: u32) -> Result<Consumer<i16>, String> {
+    let (tx, rx) = RingBuffer::new(4 * MAX_BLOCK);
+    let (ready_tx, ready_rx) = channel();
+    let device = device.map(|x| x.to_string());
+    thread::spawn(move || {
+        let done = Arc::new(AtomicBool::new(false));
+        let stream = build(device.as_deref(), rate, tx, Arc::clone(&done))
+            .and_then(|x| x.play().map(|_| x).map_err(|e| e.to_string()));
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                let _ = ready_tx.send(Err(e));
+                return;
+            }
+        };
+        let _ = ready_tx.send(Ok(()));
+        while !done.load(Ordering::Relaxed) {
+            thread::sleep(Duration::from_millis(100));
+        }
+        drop(stream);
+    });
+    match ready_rx.recv() {
+        Ok(Ok(())) => Ok(rx),
+        Ok(Err(e)) => Err(e),
+        Err(_) => Err("the input thread stopped".to_string()),
+    }
+}
+
+fn build(
+    device: Option<&str>,
+    rate: u32,
+    tx: Producer<i16>,
+    done: Arc<AtomicBool>,
+) -> Result<cpal::Stream, String> {
+    let host = out::find_host(HOST.get().map(|x| x.as_str())).map_err(|e| e.to_string())?;
+    let device = match device {
+        None => host
+            .default_input_device()
+            .ok_or_else(|| "there is no default input device".to_string())?,
+        Some(name) => {
+            let mut names = vec![];
+            let mut found = None;
+            for (i, d) in host.input_devices().map_err(|e| e.to_string())?.enumerate() {
+                let n = d.name().unwrap_or_default();
+                if name == i.to_string() || name == n {
+                    found = Some(d);
+                    break;
+                }
+                names.push(format!("{}: {}", i, n));
+            }
+            found.ok_or_else(|| {
+                format!(
+                    "no input device `{}` (expected one of: {})",
+                    name,
+                    names.join(", ")
+                )
+            })?
+        }
+    };
+    let name = device.name().unwrap_or_default();
+    let config = device
+        .supported_input_configs()
+        .map_err(|e| e.to_string())?
+        .find(|x| x.min_sample_rate().0 <= rate && rate <= x.max_sample_rate().0)
+        .ok_or_else(|| format!("{} can't record at {} Hz", name, rate))?
+        .with_sample_rate(SampleRate(rate));
+
+    let channels = config.channels() as usize;
+    let format = config.sample_format();
+    let config = config.config();
+    let on_error = |_| ();
+    match format {
+        SampleFormat::F32 => {
+            device.build_input_stream(&config, callback::<f32>(channels, tx, done), on_error)
+        }
+        SampleFormat::I16 => {
+            device.build_input_stream(&config, callback::<i16>(channels, tx, done), on_error)
+        }
+        SampleFormat::U16 => {
+            device.build_input_stream(&config, callback::<u16>(channels, tx, done), on_error)
+        }
+    }
+    .map_err(|e| e.to_string())
+}
+
+// Mixes each frame down and pushes it to the ring. The ring only fills up
+// while the patch isn't running, and then the newest samples are dropped,
+// but AudioIn skips the backlog once it starts.
+fn callback<T: Sample>(
+    channels: usize,
+    mut tx: Producer<i16>,
+    done: Arc<AtomicBool>,
+) -> impl FnMut(&[T], &cpal::InputCallbackInfo) + Send + 'static {
+    move |data: &[T], _| {
+        for frame in data.chunks(channels) {
+            let sum = frame.iter().map(|x| x.to_i16() as i32).sum::<i32>();
+            let _ = tx.push((sum / channels as i32) as i16);
+        }
+        if tx.is_abandoned() {
+            done.store(true, Ordering::Relaxed);
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use std::fs::File;
+use std::path::Path;
+
+use wav::bit_depth::BitDepth;
+
+// A stand-in for an input device: reads the whole of a WAV file, mixed
+// down to one channel, for an AudioIn to play over and over. It's read up
+// front, rather than streamed in by another thread, so that a render goes
+// through it at its own speed and always sounds the same.
+pub fn load(path: &Path, rate: u32) -> Result<Vec<i16>, String> {
+    let mut file = File::open(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let (header, data) = wav::read(&mut file).map_err(|e| format!("{}: {}", path.display(), e))?;
+    if header.sampling_rate != rate {
+        return Err(format!(
+            "{} is at {} Hz, but the patch runs at {} Hz",
+            path.display(),
+            header.sampling_rate,
+            rate
+        ));
+    }
+    let samples = match data {
+        BitDepth::Eight(x) => x.iter().map(|s| ((*s as i16) - 128) << 8).collect(),
+        BitDepth::Sixteen(x) => x,
+        BitDepth::TwentyFour(x) => x.iter().map(|s| (*s >> 8) as i16).collect(),
+        BitDepth::ThirtyTwoFloat(x) => x.iter().map(cpal::Sample::to_i16).collect(),
+        BitDepth::Empty => vec![],
+    };
+    let channels = header.channel_count.max(1) as usize;
+    let samples = samples
+        .chunks(channels)
+        .map(|frame| (frame.iter().map(|x| *x as i32).sum::<i32>() / channels as i32) as i16)
+        .collect::<Vec<i16>>();
+    if samples.is_empty() {
+        return Err(format!("{} has no samples", path.display()));
+    }
+
+    Ok(samples)
+}
@@ -0,0 +1,9 @@
+mod audio_in;
+mod cpal_in;
+mod file_in;
+
+pub use audio_in::AudioIn;
+pub use audio_in::Source;
+pub use cpal_in::open as open_device;
+pub use cpal_in::set_host;
+pub use file_in::load as load_file;
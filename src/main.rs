@@ -4,29 +4,49 @@ extern crate lazy_static;
 use std::error::Error;
 use std::io::Result as IoResult;
 use std::sync::atomic::AtomicI16;
-use std::sync::atomic::AtomicU64;
 use std::sync::mpsc::channel;
 use std::sync::Arc;
 
 use std::fs;
 use std::fs::DirEntry;
 use std::path::Path;
+use std::path::PathBuf;
+
+use argh::FromArgs;
 
 mod amp;
 mod arp;
 mod audio;
+mod engine;
 mod env;
+mod event;
 mod fixed;
+#[cfg(test)]
+mod golden;
+mod graph;
+mod groove;
+mod input;
 mod mix;
 mod osc;
 mod out;
+mod patch;
+mod patcher;
+mod poly;
+mod render;
 mod rvb;
+mod scope;
 mod seq;
+mod spectrum;
+mod transport;
 mod tui_util;
 mod ui;
 mod util;
 
 use crate::audio::spawn_audio;
+use crate::engine::Engine;
+use crate::groove::Groove;
+use crate::patch::Patch;
+use crate::scope::Scope;
 use crate::ui::ui_loop;
 use crate::ui::SingleCycleWaveFormItem;
 
@@ -53,7 +73,214 @@ fn visit_dirs(
     Ok(files)
 }
 
+#[derive(Debug, FromArgs)]
+#[argh(description = "options")]
+struct Cli {
+    /// time in ms between two ticks.
+    #[argh(option, default = "65", description = "tick rate in ms")]
+    tick_rate: u64,
+    /// whether unicode symbols are used to improve the overall look of the app
+    #[argh(option, default = "true", description = "unicode?")]
+    enhanced_graphics: bool,
+    /// the patch to play instead of patches/default.patch.
+    #[argh(option, description = "patch file to load")]
+    patch: Option<PathBuf>,
+    /// the sample rate, instead of the output device's.
+    #[argh(option, description = "sample rate in Hz")]
+    rate: Option<u32>,
+    /// the audio host to use instead of the default, e.g. ALSA or JACK.
+    #[argh(option, description = "audio host (see `jsynth devices`)")]
+    host: Option<String>,
+    /// the output device to use instead of the default.
+    #[argh(
+        option,
+        description = "output device name or index (see `jsynth devices`)"
+    )]
+    device: Option<String>,
+    /// the number of device channels, instead of the device's default.
+    #[argh(option, description = "number of device channels")]
+    channels: Option<u16>,
+    /// the size of the device's buffer, instead of its default.
+    #[argh(option, description = "buffer size in frames")]
+    buffer: Option<u32>,
+    /// what each channel of the output device plays.
+    #[argh(
+        option,
+        from_str_fn(channel_map),
+        description = "what each device channel plays: l, r, m (both) or -, e.g. l,r,-,-"
+    )]
+    channel_map: Option<Vec<out::Source>>,
+    /// dither the samples that lose bits on their way to the device.
+    #[argh(switch, description = "dither mixed-down channels")]
+    dither: bool,
+    /// how late every other 16th is, from 50 (straight) to 75.
+    #[argh(option, default = "transport::MIN_SWING", description = "swing in %")]
+    swing: f64,
+    /// a groove file, with the timing and velocity of each step.
+    #[argh(option, description = "groove file to play with")]
+    groove: Option<PathBuf>,
+    /// list the ports of each type of component, and exit.
+    #[argh(switch, description = "list component ports")]
+    ports: bool,
+    #[argh(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, FromArgs)]
+#[argh(subcommand)]
+enum Command {
+    Render(RenderArgs),
+    Devices(DevicesArgs),
+}
+
+// Lists the hosts and output devices that can be picked with --host and
+// --device.
+#[derive(Debug, FromArgs)]
+#[argh(
+    subcommand,
+    name = "devices",
+    description = "list the audio hosts and output devices"
+)]
+struct DevicesArgs {}
+
+// Renders a patch to a WAV file, without a sound device or the TUI.
+#[derive(Debug, FromArgs)]
+#[argh(
+    subcommand,
+    name = "render",
+    description = "render the patch to a WAV file"
+)]
+struct RenderArgs {
+    #[argh(positional, description = "WAV file to write")]
+    out: PathBuf,
+    #[argh(option, description = "number of bars to render (default 4)")]
+    bars: Option<usize>,
+    #[argh(option, description = "number of seconds to render, instead of bars")]
+    seconds: Option<f64>,
+    #[argh(option, default = "util::TEMPO", description = "tempo in bpm")]
+    tempo: u64,
+}
+
+fn channel_map(s: &str) -> Result<Vec<out::Source>, String> {
+    s.split(',').map(|x| x.trim().parse()).collect()
+}
+
+// Loads the patch, or exits with the reason it can't be loaded.
+fn load_patch(path: &Option<PathBuf>, rate: u32) -> Patch {
+    let patch = match path {
+        Some(path) => Patch::load(path, rate),
+        None => Patch::parse(patch::DEFAULT_PATCH, rate),
+    };
+    match patch {
+        Ok(patch) => patch,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+// Loads the groove, or exits with the reason it can't be loaded.
+fn load_groove(path: &Option<PathBuf>) -> Groove {
+    match path.as_ref().map(|x| Groove::load(x)) {
+        None => Groove::straight(),
+        Some(Ok(groove)) => groove,
+        Some(Err(e)) => {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn start_engine(patch: Patch, tempo: u64, swing: f64, groove: Groove) -> Engine {
+    let mut engine = Engine::new(patch, tempo);
+    engine.transport_mut().set_swing(swing);
+    engine.transport_mut().set_groove(groove);
+    engine
+}
+
+fn render_to_file(engine: Engine, args: RenderArgs) -> Result<(), Box<dyn Error>> {
+    let length = match (args.bars, args.seconds) {
+        (Some(_), Some(_)) => return Err("give either --bars or --seconds, not both".into()),
+        (_, Some(secs)) if secs.is_finite() && secs >= 0. => render::Length::Seconds(secs),
+        (_, Some(secs)) => return Err(format!("can't render {} seconds", secs).into()),
+        (bars, None) => render::Length::Bars(bars.unwrap_or(4)),
+    };
+    let rate = engine.rate();
+    let (samples, readings) = render::render(engine, length);
+    render::write_wav(&args.out, rate, samples)
+        .map_err(|e| format!("{}: {}", args.out.display(), e))?;
+    for (name, reading) in readings.named() {
+        println!("{:12} {}", name, reading);
+    }
+    Ok(())
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
+    let cli: Cli = argh::from_env();
+    if cli.ports {
+        print!("{}", patch::describe_ports());
+        return Ok(());
+    }
+
+    if cli.rate == Some(0) {
+        eprintln!("error: the sample rate must be more than 0 Hz");
+        std::process::exit(1);
+    }
+
+    if !(transport::MIN_SWING..=transport::MAX_SWING).contains(&cli.swing) {
+        eprintln!(
+            "error: the swing must be from {}% to {}%",
+            transport::MIN_SWING,
+            transport::MAX_SWING
+        );
+        std::process::exit(1);
+    }
+    let groove = load_groove(&cli.groove);
+
+    if let Some(Command::Devices(_)) = cli.command {
+        print!("{}", out::list_devices());
+        return Ok(());
+    }
+
+    // AudioIn records from the same host as the output plays to.
+    input::set_host(cli.host.clone());
+
+    if let Some(Command::Render(args)) = cli.command {
+        if args.tempo == 0 {
+            eprintln!("error: the tempo must be at least 1 bpm");
+            std::process::exit(1);
+        }
+        let patch = load_patch(&cli.patch, cli.rate.unwrap_or(util::DEFAULT_RATE));
+        let engine = start_engine(patch, args.tempo, cli.swing, groove);
+        if let Err(e) = render_to_file(engine, args) {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // Open the device and load the patch first so that mistakes are
+    // reported before the terminal is taken over.
+    let options = out::Options {
+        host: cli.host,
+        device: cli.device,
+        rate: cli.rate,
+        channels: cli.channels,
+        buffer: cli.buffer,
+        map: cli.channel_map,
+        dither: cli.dither,
+    };
+    let cpal_out = match out::CpalOut::open(options) {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let rate = cpal_out.rate();
+    let patch = load_patch(&cli.patch, rate);
+
     let path = Path::new("/home/jim/Downloads/AKWF/");
 
     let mut single_cycle_wave_forms =
@@ -94,18 +321,17 @@ fn main() -> Result<(), Box<dyn Error>> {
     let beat = Arc::new(AtomicI16::new(-1));
     let setbeat = Arc::clone(&beat);
 
-    let measured_xtime = Arc::new(AtomicU64::new(0));
-
-    let target_inc = (1_000_000_000. / (util::RATE as f64)) as u128; //22675; // (1/44100 * 10^9) ns //(((util::RATE as u64) / 100) as u64;
-
-    let _cpal_out = spawn_audio(rx, tx2, setbeat);
+    let mut engine = start_engine(patch, util::TEMPO, cli.swing, groove);
+    let scope = Scope::new(engine.scope(), rate);
+    let cpal_out = spawn_audio(engine, cpal_out, rx, tx2, setbeat)?;
 
     ui_loop(
         tx,
         rx2,
         beat,
-        measured_xtime,
-        target_inc,
+        cpal_out.load(),
+        scope,
         &single_cycle_wave_forms,
+        cli.tick_rate,
     )
 }
@@ -1,6 +1,56 @@
-use std::ops::{Index, IndexMut};
-
+use crate::out::Meter;
 use crate::util::Component;
+use crate::util::PortInfo;
+use crate::util::PortKind;
+
+const INPUTS: [PortInfo; 4] = [
+    PortInfo {
+        name: "a",
+        kind: PortKind::Audio,
+        unit: "",
+        min: i16::MIN,
+        max: i16::MAX,
+        default: 0,
+        description: "The first signal.",
+    },
+    PortInfo {
+        name: "a_lvl",
+        kind: PortKind::Cv,
+        unit: "level",
+        min: 0,
+        max: i16::MAX,
+        default: 0,
+        description: "The level of a. Not applied yet: a is mixed at full level.",
+    },
+    PortInfo {
+        name: "b",
+        kind: PortKind::Audio,
+        unit: "",
+        min: i16::MIN,
+        max: i16::MAX,
+        default: 0,
+        description: "The second signal.",
+    },
+    PortInfo {
+        name: "b_lvl",
+        kind: PortKind::Cv,
+        unit: "level",
+        min: 0,
+        max: i16::MAX,
+        default: 0,
+        description: "The level of b. Not applied yet: b is mixed at full level.",
+    },
+];
+
+const OUTPUTS: [PortInfo; 1] = [PortInfo {
+    name: "out",
+    kind: PortKind::Audio,
+    unit: "",
+    min: i16::MIN,
+    max: i16::MAX,
+    default: 0,
+    description: "The sum of a and b, saturated.",
+}];
 
 pub struct Mixer {
     pub a: i16,
@@ -8,7 +58,8 @@ pub struct Mixer {
     pub b: i16,
     pub b_lvl: i16,
     pub out: i16,
-    pub dummy: i16,
+    // The levels of a, b and out, for the UI.
+    pub meters: [Meter; 3],
 }
 
 impl Mixer {
@@ -19,13 +70,13 @@ impl Mixer {
             b: 0,
             b_lvl: 0,
             out: 0,
-            dummy: 0,
+            meters: Default::default(),
         }
     }
 }
 
 impl Component for Mixer {
-    fn tick(&mut self) {}
+    fn tick(&mut self, _velocity: i16) {}
     fn step(&mut self) {
         let a = self.a;
         let b = self.b;
@@ -35,35 +86,51 @@ impl Component for Mixer {
         // let b = (((self.b_lvl as i32) * (self.b as i32)) >> 15) as i16;
 
         self.out = a.saturating_add(b);
+        for (meter, s) in self.meters.iter_mut().zip([a, b, self.out]) {
+            meter.add(s);
+        }
+    }
+    fn process(&mut self, len: usize, inputs: &[Option<&[i16]>], outputs: &mut [&mut [i16]]) {
+        if len == 0 {
+            return;
+        }
+        let out = &mut outputs[0][..len];
+        for (n, o) in out.iter_mut().enumerate() {
+            let a = inputs[0].map_or(self.a, |x| x[n]);
+            let b = inputs[2].map_or(self.b, |x| x[n]);
+            *o = a.saturating_add(b);
+            for (meter, s) in self.meters.iter_mut().zip([a, b, *o]) {
+                meter.add(s);
+            }
+        }
+        self.a = inputs[0].map_or(self.a, |x| x[len - 1]);
+        self.a_lvl = inputs[1].map_or(self.a_lvl, |x| x[len - 1]);
+        self.b = inputs[2].map_or(self.b, |x| x[len - 1]);
+        self.b_lvl = inputs[3].map_or(self.b_lvl, |x| x[len - 1]);
+        self.out = out[len - 1];
     }
-    fn inputs(&self) -> Vec<&'static str> {
-        vec!["a", "a_lvl", "b", "b_lvl"]
+    fn input_info(&self) -> &'static [PortInfo] {
+        &INPUTS
     }
 
-    fn outputs(&self) -> Vec<&'static str> {
-        vec!["out"]
+    fn output_info(&self) -> &'static [PortInfo] {
+        &OUTPUTS
     }
-}
 
-impl Index<&str> for Mixer {
-    type Output = i16;
-
-    fn index(&self, i: &str) -> &Self::Output {
-        match i {
-            "out" => &self.out,
-            _ => &0,
+    fn input_mut(&mut self, slot: usize) -> &mut i16 {
+        match slot {
+            0 => &mut self.a,
+            1 => &mut self.a_lvl,
+            2 => &mut self.b,
+            3 => &mut self.b_lvl,
+            _ => panic!("Mixer has no input slot {}", slot),
         }
     }
-}
 
-impl IndexMut<&str> for Mixer {
-    fn index_mut(&mut self, i: &str) -> &mut Self::Output {
-        match i {
-            "a" => &mut self.a,
-            "a_lvl" => &mut self.a_lvl,
-            "b" => &mut self.b,
-            "b_lvl" => &mut self.b_lvl,
-            _ => &mut self.dummy,
+    fn output(&self, slot: usize) -> i16 {
+        match slot {
+            0 => self.out,
+            _ => panic!("Mixer has no output slot {}", slot),
         }
     }
 }
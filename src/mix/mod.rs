@@ -1,3 +1,5 @@
 mod mixer;
+mod pan;
 
 pub use mixer::Mixer;
+pub use pan::Pan;
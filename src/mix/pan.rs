@@ -0,0 +1,116 @@
+use crate::util::Component;
+use crate::util::PortInfo;
+use crate::util::PortKind;
+
+const INPUTS: [PortInfo; 2] = [
+    PortInfo {
+        name: "in_cv",
+        kind: PortKind::Audio,
+        unit: "",
+        min: i16::MIN,
+        max: i16::MAX,
+        default: 0,
+        description: "The signal to pan.",
+    },
+    PortInfo {
+        name: "pan",
+        kind: PortKind::Cv,
+        unit: "position",
+        min: i16::MIN,
+        max: i16::MAX,
+        default: 0,
+        description: "Where the signal sits, from min (left) through 0 (centre) to max (right).",
+    },
+];
+
+const OUTPUTS: [PortInfo; 2] = [
+    PortInfo {
+        name: "left",
+        kind: PortKind::Audio,
+        unit: "",
+        min: i16::MIN,
+        max: i16::MAX,
+        default: 0,
+        description: "The left channel.",
+    },
+    PortInfo {
+        name: "right",
+        kind: PortKind::Audio,
+        unit: "",
+        min: i16::MIN,
+        max: i16::MAX,
+        default: 0,
+        description: "The right channel.",
+    },
+];
+
+// Places a mono signal between the left and right channels, keeping its
+// loudness the same wherever it is (a constant-power pan law).
+pub struct Pan {
+    pub in_cv: i16,
+    pub pan: i16,
+    pub left: i16,
+    pub right: i16,
+    // The gains for `gains_for`, as Q1.15, so that they are only
+    // recalculated when pan moves.
+    gains: (i32, i32),
+    gains_for: Option<i16>,
+}
+
+impl Pan {
+    pub fn new() -> Self {
+        Pan {
+            in_cv: 0,
+            pan: 0,
+            left: 0,
+            right: 0,
+            gains: (0, 0),
+            gains_for: None,
+        }
+    }
+
+    fn gains(&mut self) -> (i32, i32) {
+        if self.gains_for != Some(self.pan) {
+            let angle =
+                ((self.pan as f64) - (i16::MIN as f64)) / 65535. * std::f64::consts::FRAC_PI_2;
+            self.gains = (
+                (angle.cos() * (i16::MAX as f64)) as i32,
+                (angle.sin() * (i16::MAX as f64)) as i32,
+            );
+            self.gains_for = Some(self.pan);
+        }
+        self.gains
+    }
+}
+
+impl Component for Pan {
+    fn tick(&mut self, _velocity: i16) {}
+    fn step(&mut self) {
+        let (l, r) = self.gains();
+        self.left = ((l * (self.in_cv as i32)) >> 15) as i16;
+        self.right = ((r * (self.in_cv as i32)) >> 15) as i16;
+    }
+    fn input_info(&self) -> &'static [PortInfo] {
+        &INPUTS
+    }
+
+    fn output_info(&self) -> &'static [PortInfo] {
+        &OUTPUTS
+    }
+
+    fn input_mut(&mut self, slot: usize) -> &mut i16 {
+        match slot {
+            0 => &mut self.in_cv,
+            1 => &mut self.pan,
+            _ => panic!("Pan has no input slot {}", slot),
+        }
+    }
+
+    fn output(&self, slot: usize) -> i16 {
+        match slot {
+            0 => self.left,
+            1 => self.right,
+            _ => panic!("Pan has no output slot {}", slot),
+        }
+    }
+}
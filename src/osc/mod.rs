@@ -1,3 +1,4 @@
 mod wave_table_osc;
 
+pub use wave_table_osc::WaveTableChoice;
 pub use wave_table_osc::WaveTableOsc;
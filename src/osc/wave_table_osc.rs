@@ -1,13 +1,16 @@
-use std::ops::{Index, IndexMut};
-
 extern crate rand;
 
+use rand::rngs::StdRng;
+use rand::Rng;
+use rand::SeedableRng;
 use std::fs::File;
-use std::path::PathBuf;
+use std::path::Path;
 use wav;
 use wav::bit_depth::BitDepth;
 
 use crate::util::Component;
+use crate::util::PortInfo;
+use crate::util::PortKind;
 use crate::util::WAVE_TABLE_SAMPLES_PER_CYCLE;
 use crate::util::WAVE_TABLE_SAMPLES_PER_CYCLE_FACTOR;
 lazy_static! {
@@ -66,13 +69,18 @@ lazy_static! {
         }
         wt
     };
-    static ref WHITE_NOISE_TABLE: [i16; WAVE_TABLE_SAMPLES_PER_CYCLE as usize] = {
-        let mut wt = [0i16; WAVE_TABLE_SAMPLES_PER_CYCLE as usize];
-        for i in 1..WAVE_TABLE_SAMPLES_PER_CYCLE {
-            wt[i as usize] = wt[(i - 1) as usize].wrapping_add(rand::random());
-        }
-        wt
-    };
+    static ref WHITE_NOISE_TABLE: [i16; WAVE_TABLE_SAMPLES_PER_CYCLE as usize] =
+        white_noise_table(rand::random);
+}
+
+fn white_noise_table(
+    mut random: impl FnMut() -> i16,
+) -> [i16; WAVE_TABLE_SAMPLES_PER_CYCLE as usize] {
+    let mut wt = [0i16; WAVE_TABLE_SAMPLES_PER_CYCLE as usize];
+    for i in 1..WAVE_TABLE_SAMPLES_PER_CYCLE {
+        wt[i as usize] = wt[(i - 1) as usize].wrapping_add(random());
+    }
+    wt
 }
 
 #[derive(Clone, Eq, PartialEq, Debug)]
@@ -86,6 +94,46 @@ pub enum WaveTableChoice {
     Custom(Vec<i16>),
 }
 
+const INPUTS: [PortInfo; 3] = [
+    PortInfo {
+        name: "freq",
+        kind: PortKind::Pitch,
+        unit: "note",
+        min: 0,
+        max: 127,
+        default: 69,
+        description: "The note to play, as a MIDI note number (69 is A440).",
+    },
+    PortInfo {
+        name: "modulation_idx",
+        kind: PortKind::Cv,
+        unit: "level",
+        min: i16::MIN,
+        max: i16::MAX,
+        default: 0,
+        description: "How far modulation can bend the pitch, as a fraction of freq.",
+    },
+    PortInfo {
+        name: "modulation",
+        kind: PortKind::Audio,
+        unit: "",
+        min: i16::MIN,
+        max: i16::MAX,
+        default: 0,
+        description: "The signal to frequency modulate by.",
+    },
+];
+
+const OUTPUTS: [PortInfo; 1] = [PortInfo {
+    name: "out",
+    kind: PortKind::Audio,
+    unit: "",
+    min: i16::MIN,
+    max: i16::MAX,
+    default: 0,
+    description: "The wavetable at the current phase.",
+}];
+
 pub struct WaveTableOsc {
     pub counter: u32,
     pub wt_i: u32,
@@ -96,7 +144,6 @@ pub struct WaveTableOsc {
     pub modulation: i16,
     pub phase_offset: i16,
     pub out_cv: i16,
-    pub dummy: i16,
     ipc_64_map: [u32; 256],
 }
 
@@ -116,38 +163,36 @@ impl WaveTableOsc {
             modulation: 0,
             phase_offset: 0,
             out_cv: 0,
-            dummy: 0,
             ipc_64_map: ipc_64_map,
             which_table: which_table,
         }
     }
 
-    pub fn load_scwf(filename: PathBuf) -> WaveTableChoice {
-        let mut inp_file = File::open(filename).unwrap();
-        let (_header, data) = wav::read(&mut inp_file).unwrap();
+    // Reads a single cycle waveform from a 16 bit WAV file. This reads a
+    // file and allocates, so it's done off the audio thread, which is then
+    // handed the table.
+    pub fn load_scwf(filename: &Path) -> Result<WaveTableChoice, String> {
+        let mut inp_file =
+            File::open(filename).map_err(|e| format!("{}: {}", filename.display(), e))?;
+        let (_header, data) =
+            wav::read(&mut inp_file).map_err(|e| format!("{}: {}", filename.display(), e))?;
 
-        let len = match &data {
-            BitDepth::Eight(fwt) => fwt.len(),
-            BitDepth::Sixteen(fwt) => fwt.len(),
-            BitDepth::TwentyFour(fwt) => fwt.len(),
-            BitDepth::ThirtyTwoFloat(fwt) => fwt.len(),
-            BitDepth::Empty => 0,
-        }
-        .min(WAVE_TABLE_SAMPLES_PER_CYCLE as usize);
-
-        let mut wt = vec![0; len];
+        // Short files are padded with silence, so that the oscillator can
+        // index the whole table.
+        let mut wt = vec![0; WAVE_TABLE_SAMPLES_PER_CYCLE as usize];
         match data {
-            BitDepth::Eight(_) => println!("8"),
             BitDepth::Sixteen(fwt) => {
-                for i in 1..len {
-                    wt[i as usize] = fwt[i as usize];
-                }
+                let len = fwt.len().clamp(1, wt.len());
+                wt[1..len].copy_from_slice(&fwt[1..len]);
+            }
+            _ => {
+                return Err(format!(
+                    "{}: only 16 bit samples are supported",
+                    filename.display()
+                ))
             }
-            BitDepth::TwentyFour(_) => println!("24"),
-            BitDepth::ThirtyTwoFloat(_) => println!("32"),
-            BitDepth::Empty => println!("0"),
         };
-        WaveTableChoice::Custom(wt)
+        Ok(WaveTableChoice::Custom(wt))
     }
 
     pub fn sin(ipc_64_map: [u32; 256], init_freq: i16) -> WaveTableOsc {
@@ -196,12 +241,27 @@ impl WaveTableOsc {
             WaveTableChoice::WhiteNoise,
         )
     }
+
+    // White noise that is the same every time for the same seed, unlike
+    // the shared table that white_noise plays.
+    pub fn seeded_white_noise(ipc_64_map: [u32; 256], init_freq: i16, seed: u64) -> WaveTableOsc {
+        let mut rng = StdRng::seed_from_u64(seed);
+        WaveTableOsc::new(
+            [0; WAVE_TABLE_SAMPLES_PER_CYCLE as usize],
+            ipc_64_map,
+            init_freq,
+            WaveTableChoice::Custom(white_noise_table(|| rng.gen()).to_vec()),
+        )
+    }
 }
 
 impl Component for WaveTableOsc {
-    fn tick(&mut self) {}
+    fn tick(&mut self, _velocity: i16) {}
     fn step(&mut self) {
-        let freq_ipc = self.ipc_64_map[self.freq as usize];
+        // A wire can carry any value, but notes past the end of the
+        // increment map would stall the oscillator, or index past it.
+        let freq = self.freq.clamp(INPUTS[0].min, INPUTS[0].max);
+        let freq_ipc = self.ipc_64_map[freq as usize];
         // So, in theory we could have a wt with multiple frames in it,
         // so I don't want to hardcode this right now.
         // let wt_len = (64 * self.wt.len()) as u32;
@@ -250,34 +310,50 @@ impl Component for WaveTableOsc {
         };
     }
 
-    fn inputs(&self) -> Vec<&'static str> {
-        vec!["modulation_idx", "modulation"]
+    fn input_info(&self) -> &'static [PortInfo] {
+        &INPUTS
     }
 
-    fn outputs(&self) -> Vec<&'static str> {
-        vec!["out"]
+    fn output_info(&self) -> &'static [PortInfo] {
+        &OUTPUTS
     }
-}
 
-impl Index<&str> for WaveTableOsc {
-    type Output = i16;
+    fn input_mut(&mut self, slot: usize) -> &mut i16 {
+        match slot {
+            0 => &mut self.freq,
+            1 => &mut self.modulation_idx,
+            2 => &mut self.modulation,
+            _ => panic!("WaveTableOsc has no input slot {}", slot),
+        }
+    }
 
-    fn index(&self, i: &str) -> &Self::Output {
-        match i {
-            "out" => &self.out_cv,
-            _ => &0,
+    fn output(&self, slot: usize) -> i16 {
+        match slot {
+            0 => self.out_cv,
+            _ => panic!("WaveTableOsc has no output slot {}", slot),
         }
     }
 }
 
-impl IndexMut<&str> for WaveTableOsc {
-    fn index_mut(&mut self, i: &str) -> &mut Self::Output {
-        match i {
-            "freq" => &mut self.freq,
-            "modulation" => &mut self.modulation,
-            "modulation_idx" => &mut self.modulation_idx,
-            // This should probably error.
-            _ => &mut self.dummy,
-        }
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+    use crate::engine::CHANNELS;
+    use crate::patch::Patch;
+    use crate::util::DEFAULT_RATE;
+
+    // An oscillator wired into another's freq swings it through every
+    // value, from i16::MIN to i16::MAX, and the notes past 127.
+    #[test]
+    fn wired_freq_is_clamped_to_the_notes() {
+        let patch = "wto1 = WaveTableOsc table=saw
+                     wto1.freq = 60
+                     wto2 = WaveTableOsc
+                     wto1.out -> wto2.freq
+                     out wto2.out";
+        let mut engine = Engine::new(Patch::parse(patch, DEFAULT_RATE).unwrap(), 480);
+        let mut out = vec![0; 4096 * CHANNELS];
+        engine.render(&mut out);
+        assert!(out.iter().any(|x| *x != 0));
     }
 }
@@ -3,49 +3,408 @@ use anyhow;
 use cpal::traits::DeviceTrait;
 use cpal::traits::HostTrait;
 use cpal::traits::StreamTrait;
+use cpal::BufferSize;
+use cpal::SampleFormat;
+use cpal::SampleRate;
+use cpal::SupportedBufferSize;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
 
+use crate::engine::CHANNELS;
+use crate::engine::MAX_BLOCK;
+
+use super::Load;
+
+// What a device channel plays: one of the engine's channels, all of them
+// mixed down, or nothing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source {
+    Channel(usize),
+    Mix,
+    Silent,
+}
+
+impl FromStr for Source {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "l" => Ok(Source::Channel(0)),
+            "r" => Ok(Source::Channel(1)),
+            "m" => Ok(Source::Mix),
+            "-" => Ok(Source::Silent),
+            _ => Err(format!(
+                "unknown channel `{}` (expected l, r, m for both, or - for none)",
+                s
+            )),
+        }
+    }
+}
+
+impl Source {
+    // The sample for a frame, on the engine's scale of -32768 to 32767. A
+    // mix has more resolution than the engine's samples.
+    fn sample(&self, frame: &[i16]) -> f32 {
+        match self {
+            Source::Channel(c) => frame[*c] as f32,
+            Source::Mix => frame.iter().map(|x| *x as f32).sum::<f32>() / frame.len() as f32,
+            Source::Silent => 0.,
+        }
+    }
+}
+
+// Triangular (TPDF) dither: noise of up to 1 step either way, added to
+// a sample before it's rounded to fewer bits, so that the rounding error
+// becomes a steady hiss rather than distortion that follows the signal.
+// The noise comes from a xorshift generator, which is cheap enough for the
+// audio thread.
+struct Dither(u32);
+
+impl Dither {
+    fn next(&mut self) -> f32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0 as f32 / u32::MAX as f32
+    }
+
+    fn tpdf(&mut self) -> f32 {
+        self.next() + self.next() - 1.
+    }
+}
+
+// A device sample format, made from a sample on the engine's scale.
+trait DeviceSample: cpal::Sample + Send + 'static {
+    fn from_engine(x: f32, dither: Option<&mut Dither>) -> Self;
+}
+
+fn quantize(x: f32, dither: Option<&mut Dither>) -> i16 {
+    let x = x + dither.map_or(0., |d| d.tpdf());
+    x.round().clamp(i16::MIN as f32, i16::MAX as f32) as i16
+}
+
+impl DeviceSample for f32 {
+    fn from_engine(x: f32, _: Option<&mut Dither>) -> Self {
+        x / 32768.
+    }
+}
+
+impl DeviceSample for i16 {
+    fn from_engine(x: f32, dither: Option<&mut Dither>) -> Self {
+        quantize(x, dither)
+    }
+}
+
+impl DeviceSample for u16 {
+    fn from_engine(x: f32, dither: Option<&mut Dither>) -> Self {
+        (quantize(x, dither) as i32 + 32768) as u16
+    }
+}
+
+// Which device to open, and how. Anything left as None is up to the
+// device.
+#[derive(Debug, Default)]
+pub struct Options {
+    pub host: Option<String>,
+    // A name, or an index from `list_devices`.
+    pub device: Option<String>,
+    pub rate: Option<u32>,
+    pub channels: Option<u16>,
+    // In frames.
+    pub buffer: Option<u32>,
+    // What each of the device's channels plays.
+    pub map: Option<Vec<Source>>,
+    // Whether to dither samples that lose bits on their way to the device.
+    pub dither: bool,
+}
+
+// The output device. It is opened before the patch is built, so that the
+// components can be built for its sample rate, and started with `play`.
 #[allow(dead_code)]
 pub struct CpalOut {
-    stream: Arc<dyn StreamTrait>,
-    dummy: i16,
+    device: cpal::Device,
+    config: cpal::SupportedStreamConfig,
+    buffer: BufferSize,
+    // What each of the device's channels plays.
+    map: Vec<Source>,
+    dither: bool,
+    load: Arc<Load>,
+    stream: Option<cpal::Stream>,
 }
 
 impl CpalOut {
-    pub fn from_defaults<F>(mut next_sample: F) -> anyhow::Result<CpalOut>
+    // Opens the device in `options`, or the host's default device. Without
+    // a map, a mono device plays both channels mixed down, and any channels
+    // after the left and right are silent.
+    pub fn open(options: Options) -> anyhow::Result<CpalOut> {
+        let host = find_host(options.host.as_deref())?;
+        let device = find_device(&host, options.device.as_deref())?;
+        let name = device.name().unwrap_or_default();
+
+        let default = device.default_output_config()?;
+        let rate = options.rate.unwrap_or(default.sample_rate().0);
+        let channels = options.channels.unwrap_or(default.channels());
+        let config = if rate == default.sample_rate().0 && channels == default.channels() {
+            default
+        } else {
+            device
+                .supported_output_configs()?
+                .filter(|x| {
+                    x.channels() == channels
+                        && x.min_sample_rate().0 <= rate
+                        && rate <= x.max_sample_rate().0
+                })
+                // Any format will do, but the default's is likely best.
+                .max_by_key(|x| x.sample_format() == default.sample_format())
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "{} can't play {} channels at {} Hz (see `jsynth devices`)",
+                        name,
+                        channels,
+                        rate
+                    )
+                })?
+                .with_sample_rate(SampleRate(rate))
+        };
+
+        let buffer = match (options.buffer, config.buffer_size()) {
+            (None, _) => BufferSize::Default,
+            (Some(frames), SupportedBufferSize::Range { min, max })
+                if frames < *min || *max < frames =>
+            {
+                anyhow::bail!(
+                    "{} takes buffers of {} to {} frames, not {}",
+                    name,
+                    min,
+                    max,
+                    frames
+                )
+            }
+            (Some(frames), _) => BufferSize::Fixed(frames),
+        };
+
+        let channels = config.channels() as usize;
+        let map = match options.map {
+            Some(map) if map.len() != channels => anyhow::bail!(
+                "{} has {} channels, but the channel map has {}",
+                name,
+                channels,
+                map.len()
+            ),
+            Some(map) => map,
+            None if channels == 1 => vec![Source::Mix],
+            None => (0..channels)
+                .map(|c| {
+                    if c < CHANNELS {
+                        Source::Channel(c)
+                    } else {
+                        Source::Silent
+                    }
+                })
+                .collect(),
+        };
+
+        Ok(CpalOut {
+            device,
+            config,
+            buffer,
+            map,
+            dither: options.dither,
+            load: Arc::new(Load::default()),
+            stream: None,
+        })
+    }
+
+    // The device and how it's set up, e.g. for a status line.
+    pub fn describe(&self) -> String {
+        format!(
+            "{}, {} Hz, {} channels, {:?}",
+            self.device.name().unwrap_or_default(),
+            self.rate(),
+            self.config.channels(),
+            self.config.sample_format()
+        )
+    }
+
+    pub fn rate(&self) -> u32 {
+        self.config.sample_rate().0
+    }
+
+    // How long the callback takes to fill each buffer, once playing.
+    pub fn load(&self) -> Arc<Load> {
+        Arc::clone(&self.load)
+    }
+
+    // `render` fills a buffer with the next frames to play, as the engine
+    // does. Errors from the stream, once it's playing, go to `on_error`.
+    pub fn play<F, E>(&mut self, render: F, on_error: E) -> anyhow::Result<()>
     where
-        F: FnMut() -> i16 + Send + 'static,
+        F: FnMut(&mut [i16]) + Send + 'static,
+        E: FnMut(String) + Send + 'static,
     {
-        let host = cpal::default_host();
+        let stream = match self.config.sample_format() {
+            SampleFormat::F32 => self.build::<f32, F, E>(render, on_error)?,
+            SampleFormat::I16 => self.build::<i16, F, E>(render, on_error)?,
+            SampleFormat::U16 => self.build::<u16, F, E>(render, on_error)?,
+        };
+        stream.play()?;
 
-        let device = host
-            .default_output_device()
-            .expect("failed to find output device");
+        self.stream = Some(stream);
+        Ok(())
+    }
 
-        let config = device.default_output_config().unwrap();
-        let channels = config.channels() as usize;
-        println!("Output device: {}", device.name()?);
-        println!("Default output config: {:?}", config);
-
-        let write_data = move |output: &mut [f32], _cbi: &cpal::OutputCallbackInfo| {
-            for frame in output.chunks_mut(channels) {
-                let samp = next_sample();
-                let s = (((samp) as f64) / (i16::min_value() as f64)) as f32;
-                for sample in frame.iter_mut() {
-                    *sample = s;
+    fn build<T, F, E>(&self, mut render: F, mut on_error: E) -> anyhow::Result<cpal::Stream>
+    where
+        T: DeviceSample,
+        F: FnMut(&mut [i16]) + Send + 'static,
+        E: FnMut(String) + Send + 'static,
+    {
+        let channels = self.config.channels() as usize;
+        let map = self.map.clone();
+        // Only a mix has bits to lose, and only on its way to an integer
+        // format.
+        let dithered = map
+            .iter()
+            .map(|x| self.dither && *x == Source::Mix && T::FORMAT != SampleFormat::F32)
+            .collect::<Vec<bool>>();
+        let mut dither = Dither(0x9e37_79b9);
+        let load = Arc::clone(&self.load);
+        let rate = self.rate() as f64;
+
+        // Allocated up front so that the callback doesn't have to.
+        let mut block = vec![0i16; MAX_BLOCK * CHANNELS];
+        let write_data = move |output: &mut [T], _cbi: &cpal::OutputCallbackInfo| {
+            let started = Instant::now();
+            for frames in output.chunks_mut(channels * MAX_BLOCK) {
+                let block = &mut block[..frames.len() / channels * CHANNELS];
+                render(block);
+                for (frame, samps) in frames.chunks_mut(channels).zip(block.chunks(CHANNELS)) {
+                    for (c, sample) in frame.iter_mut().enumerate() {
+                        let d = if dithered[c] { Some(&mut dither) } else { None };
+                        *sample = T::from_engine(map[c].sample(samps), d);
+                    }
                 }
             }
+            let budget = Duration::from_secs_f64((output.len() / channels) as f64 / rate);
+            load.record(started.elapsed(), budget);
         };
 
-        println!("{:?}", config.config());
-        let stream = device.build_output_stream(&config.into(), write_data, move |e| {
-            println!("{}", e);
-        })?;
-        stream.play()?;
+        let config = cpal::StreamConfig {
+            buffer_size: self.buffer.clone(),
+            ..self.config.config()
+        };
+        Ok(self
+            .device
+            .build_output_stream(&config, write_data, move |e| on_error(e.to_string()))?)
+    }
+}
 
-        Ok(CpalOut {
-            stream: Arc::new(stream),
-            dummy: 0,
-        })
+pub fn find_host(name: Option<&str>) -> anyhow::Result<cpal::Host> {
+    let name = match name {
+        Some(name) => name,
+        None => return Ok(cpal::default_host()),
+    };
+    let hosts = cpal::available_hosts();
+    match hosts.iter().find(|x| x.name().eq_ignore_ascii_case(name)) {
+        Some(id) => Ok(cpal::host_from_id(*id)?),
+        None => anyhow::bail!(
+            "unknown host `{}` (expected one of: {})",
+            name,
+            hosts
+                .iter()
+                .map(|x| x.name())
+                .collect::<Vec<&str>>()
+                .join(", ")
+        ),
+    }
+}
+
+fn find_device(host: &cpal::Host, device: Option<&str>) -> anyhow::Result<cpal::Device> {
+    let device = match device {
+        Some(device) => device,
+        None => {
+            return host.default_output_device().ok_or_else(|| {
+                anyhow::anyhow!("{} has no default output device", host.id().name())
+            })
+        }
+    };
+    let mut names = vec![];
+    for (i, d) in host.output_devices()?.enumerate() {
+        let name = d.name().unwrap_or_default();
+        if device == i.to_string() || device == name {
+            return Ok(d);
+        }
+        names.push(format!("{}: {}", i, name));
+    }
+    anyhow::bail!(
+        "no output device `{}` on {} (expected one of: {})",
+        device,
+        host.id().name(),
+        names.join(", ")
+    )
+}
+
+// A listing of the hosts, their output devices and what each device can
+// play, for `jsynth devices`.
+pub fn list_devices() -> String {
+    let mut text = String::new();
+    let default_host = cpal::default_host().id();
+    for id in cpal::available_hosts() {
+        let default = if id == default_host { " (default)" } else { "" };
+        text += &format!("{}{}\n", id.name(), default);
+        let host = match cpal::host_from_id(id) {
+            Ok(host) => host,
+            Err(e) => {
+                text += &format!("  {}\n", e);
+                continue;
+            }
+        };
+        let default_device = host.default_output_device().and_then(|x| x.name().ok());
+        let devices = match host.output_devices() {
+            Ok(devices) => devices,
+            Err(e) => {
+                text += &format!("  {}\n", e);
+                continue;
+            }
+        };
+        let mut devices = devices.peekable();
+        if devices.peek().is_none() {
+            text += "  no output devices\n";
+        }
+        for (i, device) in devices.enumerate() {
+            let name = device.name().unwrap_or_default();
+            let default = if Some(&name) == default_device.as_ref() {
+                " (default)"
+            } else {
+                ""
+            };
+            text += &format!("  {}: {}{}\n", i, name, default);
+            let configs = match device.supported_output_configs() {
+                Ok(configs) => configs,
+                Err(e) => {
+                    text += &format!("       {}\n", e);
+                    continue;
+                }
+            };
+            for c in configs {
+                let buffer = match c.buffer_size() {
+                    SupportedBufferSize::Range { min, max } => {
+                        format!(", buffers of {} to {} frames", min, max)
+                    }
+                    SupportedBufferSize::Unknown => String::new(),
+                };
+                text += &format!(
+                    "       {} channels, {} to {} Hz, {:?}{}\n",
+                    c.channels(),
+                    c.min_sample_rate().0,
+                    c.max_sample_rate().0,
+                    c.sample_format(),
+                    buffer
+                );
+            }
+        }
     }
+    text
 }
@@ -0,0 +1,47 @@
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+// How much of each buffer's real-time budget, the time it takes to play,
+// was spent rendering it. Written by the audio callback and read by the UI,
+// so it's all atomics. Loads are fractions, 1 being the whole budget, and
+// are stored as f32 bits.
+#[derive(Default)]
+pub struct Load {
+    // Smoothed over the last few buffers.
+    load: AtomicU32,
+    // The most of any one buffer since the start.
+    peak: AtomicU32,
+    // Buffers that took longer to render than they last. It's a guess at
+    // the underruns, not a count of them: the device may have had enough
+    // queued to cover it, or run out for reasons of its own.
+    overruns: AtomicU64,
+}
+
+impl Load {
+    // Records a buffer that took `busy` to render and lasts `budget`.
+    pub fn record(&self, busy: Duration, budget: Duration) {
+        let load = busy.as_secs_f32() / budget.as_secs_f32().max(f32::EPSILON);
+        let smoothed = 0.9 * self.load() + 0.1 * load;
+        self.load.store(smoothed.to_bits(), Ordering::Relaxed);
+        if load > self.peak() {
+            self.peak.store(load.to_bits(), Ordering::Relaxed);
+        }
+        if load > 1. {
+            self.overruns.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn load(&self) -> f32 {
+        f32::from_bits(self.load.load(Ordering::Relaxed))
+    }
+
+    pub fn peak(&self) -> f32 {
+        f32::from_bits(self.peak.load(Ordering::Relaxed))
+    }
+
+    pub fn overruns(&self) -> u64 {
+        self.overruns.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,183 @@
+use std::fmt;
+
+use crate::engine::CHANNELS;
+
+// The level of a signal: its peak and RMS since the last reading, and how
+// many samples have clipped since the start, or since the clip count was
+// cleared. A sample counts as clipped when it and the one before it are
+// both at full scale, which is where saturating arithmetic leaves a signal
+// that's too loud; a wave whose peaks merely touch full scale doesn't.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Meter {
+    peak: i32,
+    sum_sq: u64,
+    count: u64,
+    clips: u64,
+    at_full_scale: bool,
+}
+
+impl Meter {
+    pub fn add(&mut self, s: i16) {
+        let s = s as i32;
+        self.peak = self.peak.max(s.abs());
+        self.sum_sq += (s * s) as u64;
+        self.count += 1;
+        let full = s >= i16::MAX as i32 || s <= -(i16::MAX as i32);
+        if full && self.at_full_scale {
+            self.clips += 1;
+        }
+        self.at_full_scale = full;
+    }
+
+    // Reads the peak and RMS, and starts them over. The clips stay.
+    pub fn take(&mut self) -> Reading {
+        let full = i16::MAX as f32;
+        let reading = Reading {
+            peak: self.peak as f32 / full,
+            rms: match self.count {
+                0 => 0.,
+                n => (self.sum_sq as f64 / n as f64).sqrt() as f32 / full,
+            },
+            clips: self.clips,
+        };
+        self.peak = 0;
+        self.sum_sq = 0;
+        self.count = 0;
+        reading
+    }
+
+    pub fn clear_clips(&mut self) {
+        self.clips = 0;
+    }
+}
+
+// A meter's levels, as fractions of full scale.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Reading {
+    pub peak: f32,
+    pub rms: f32,
+    pub clips: u64,
+}
+
+impl Reading {
+    pub fn peak_db(&self) -> f32 {
+        db(self.peak)
+    }
+
+    pub fn rms_db(&self) -> f32 {
+        db(self.rms)
+    }
+}
+
+impl fmt::Display for Reading {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "peak {:6.1} dB  rms {:6.1} dB  clips {}",
+            self.peak_db(),
+            self.rms_db(),
+            self.clips
+        )
+    }
+}
+
+// Down to -100 dB, rather than minus infinity for silence.
+fn db(x: f32) -> f32 {
+    20. * x.max(1e-5).log10()
+}
+
+// The names of what's metered: the master channels, then each mixer's
+// inputs and output.
+pub const MASTER_METERS: [&str; CHANNELS] = ["left", "right"];
+pub const MIXER_METERS: [&str; 3] = ["a", "b", "out"];
+
+// The readings of the master, and of each mixer. The mixers are listed by
+// the names of all the components, so that the audio thread can fill them
+// in without allocating; only the mixers have readings.
+#[derive(Debug, Clone, Default)]
+pub struct Readings {
+    pub master: [Reading; CHANNELS],
+    pub mixers: Vec<(String, Option<[Reading; 3]>)>,
+}
+
+impl Readings {
+    pub fn new(names: Vec<String>) -> Readings {
+        Readings {
+            master: Default::default(),
+            mixers: names.into_iter().map(|x| (x, None)).collect(),
+        }
+    }
+
+    // Each reading, named e.g. `master.left` or `mix1.a`.
+    pub fn named(&self) -> Vec<(String, Reading)> {
+        let master = MASTER_METERS
+            .iter()
+            .zip(self.master)
+            .map(|(port, r)| (format!("master.{}", port), r));
+        let mixers = self.mixers.iter().flat_map(|(name, r)| {
+            r.iter().flat_map(move |r| {
+                MIXER_METERS
+                    .iter()
+                    .zip(r)
+                    .map(move |(port, r)| (format!("{}.{}", name, port), *r))
+            })
+        });
+        master.chain(mixers).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::patch::Patch;
+    use crate::util::DEFAULT_RATE;
+
+    const LEN: usize = 8192;
+
+    #[test]
+    fn meters_read_the_levels_and_latch_clips() {
+        let patch = "wto1 = WaveTableOsc table=sin
+                     wto1.freq = 69
+                     vca1 = Vca
+                     vca1.amp_cv = 16384
+                     wto2 = WaveTableOsc table=saw
+                     wto2.freq = 60
+                     mix1 = Mixer
+                     mix2 = Mixer
+                     wto1.out -> vca1.in_cv
+                     vca1.out -> mix1.a
+                     wto2.out -> mix2.a
+                     wto2.out -> mix2.b
+                     out mix1.out mix2.out";
+        let mut engine = Engine::new(Patch::parse(patch, DEFAULT_RATE).unwrap(), 480);
+        let mut out = vec![0; LEN * CHANNELS];
+        engine.render(&mut out);
+        let readings = engine.readings().named();
+        let reading = |name: &str| readings.iter().find(|x| x.0 == name).unwrap().1;
+
+        // A sine at half scale peaks at -6 dB, and its RMS is 3 dB below that.
+        let sine = reading("mix1.a");
+        assert!((-6.5..-5.5).contains(&sine.peak_db()), "{}", sine);
+        assert!((-9.5..-8.5).contains(&sine.rms_db()), "{}", sine);
+        assert_eq!(sine.clips, 0);
+        assert_eq!(reading("mix1.b").peak, 0.);
+        assert_eq!(reading("master.left"), sine);
+
+        // Two full scale saws sum to more than full scale, and the mixer's
+        // output clips, but neither of its inputs does.
+        let sum = reading("mix2.out");
+        assert!(sum.clips > 0, "{}", sum);
+        assert_eq!(reading("mix2.a").clips, 0);
+        assert_eq!(reading("master.right").clips, sum.clips);
+
+        // The peaks start over with each reading, but the clips stay until
+        // they're cleared.
+        let readings = engine.readings().named();
+        let again = readings.iter().find(|x| x.0 == "mix2.out").unwrap().1;
+        assert_eq!(again.peak, 0.);
+        assert_eq!(again.clips, sum.clips);
+        engine.clear_clips();
+        assert_eq!(engine.readings().master[1].clips, 0);
+    }
+}
@@ -1,3 +1,12 @@
 mod cpal_out;
+mod load;
+mod meter;
 
+pub use cpal_out::find_host;
+pub use cpal_out::list_devices;
 pub use cpal_out::CpalOut;
+pub use cpal_out::Options;
+pub use cpal_out::Source;
+pub use load::Load;
+pub use meter::Meter;
+pub use meter::Readings;
@@ -0,0 +1,140 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use crate::patch::PortRef;
+use crate::util::PortError;
+use crate::util::PortInfo;
+
+#[derive(Debug)]
+pub enum PatchError {
+    Io(PathBuf, io::Error),
+    Syntax {
+        line: usize,
+        msg: String,
+    },
+    UnknownComponentType {
+        line: usize,
+        name: String,
+        kind: String,
+        valid: Vec<&'static str>,
+    },
+    DuplicateName {
+        line: usize,
+        name: String,
+    },
+    UnknownComponent {
+        line: usize,
+        name: String,
+    },
+    UnknownPort {
+        line: usize,
+        component: String,
+        err: PortError,
+    },
+    OutOfRange {
+        line: usize,
+        component: String,
+        port: PortInfo,
+        value: i16,
+    },
+    BadArgument {
+        line: usize,
+        component: String,
+        msg: String,
+    },
+    MissingOutput,
+    // Errors from changing a patch while it plays, with line 0 for the
+    // errors above.
+    AlreadyWired(PortRef, PortRef),
+    NotWired(PortRef, PortRef),
+    RemovingOutput(String),
+    Busy,
+}
+
+// Where an error is: nothing for line 0, which is used for changes that
+// aren't read from a file.
+struct At(usize);
+
+impl fmt::Display for At {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            0 => Ok(()),
+            line => write!(f, "line {}: ", line),
+        }
+    }
+}
+
+fn wire(src: &PortRef, dst: &PortRef) -> String {
+    format!("{}.{} -> {}.{}", src.0, src.1, dst.0, dst.1)
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatchError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            PatchError::Syntax { line, msg } => write!(f, "{}{}", At(*line), msg),
+            PatchError::UnknownComponentType {
+                line,
+                name,
+                kind,
+                valid,
+            } => write!(
+                f,
+                "{}unknown component type `{}` for `{}` (expected one of: {})",
+                At(*line),
+                kind,
+                name,
+                valid.join(", ")
+            ),
+            PatchError::DuplicateName { line, name } => {
+                write!(f, "{}`{}` is already declared", At(*line), name)
+            }
+            PatchError::UnknownComponent { line, name } => write!(
+                f,
+                "{}unknown component `{}` (components must be declared before use)",
+                At(*line),
+                name
+            ),
+            PatchError::UnknownPort {
+                line,
+                component,
+                err,
+            } => write!(f, "{}`{}` has {}", At(*line), component, err),
+            PatchError::OutOfRange {
+                line,
+                component,
+                port,
+                value,
+            } => write!(
+                f,
+                "{}{} is out of range for `{}.{}` ({} to {} {})",
+                At(*line),
+                value,
+                component,
+                port.name,
+                port.min,
+                port.max,
+                port.unit
+            ),
+            PatchError::BadArgument {
+                line,
+                component,
+                msg,
+            } => write!(f, "{}`{}`: {}", At(*line), component, msg),
+            PatchError::MissingOutput => write!(f, "no `out name.port` line in the patch"),
+            PatchError::AlreadyWired(src, dst) => {
+                write!(f, "`{}` is already wired", wire(src, dst))
+            }
+            PatchError::NotWired(src, dst) => write!(f, "there is no wire `{}`", wire(src, dst)),
+            PatchError::RemovingOutput(name) => write!(
+                f,
+                "`{}` can't be removed while it's the output of the patch",
+                name
+            ),
+            PatchError::Busy => write!(f, "too many changes waiting for the audio thread"),
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
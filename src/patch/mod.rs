@@ -0,0 +1,711 @@
+mod error;
+
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+pub use error::PatchError;
+
+use crate::amp;
+use crate::arp;
+use crate::audio::AvailableComponents;
+use crate::engine::CHANNELS;
+use crate::env;
+use crate::input;
+use crate::mix;
+use crate::osc;
+use crate::poly;
+use crate::rvb;
+use crate::seq;
+use crate::util;
+use crate::util::PortError;
+
+pub const DEFAULT_PATCH: &str = include_str!("../../patches/default.patch");
+
+const COMPONENT_TYPES: [&str; 11] = [
+    "Adsr",
+    "AudioIn",
+    "BasicArp",
+    "BasicSeq",
+    "Clock",
+    "Mixer",
+    "Pan",
+    "Vca",
+    "WaveTableOsc",
+    "BasicReverb",
+    "Voices",
+];
+
+pub type PortRef = (String, String);
+
+// The components, wires, and output of a patch file. See
+// patches/default.patch for the format.
+pub struct Patch {
+    pub components: Vec<(String, AvailableComponents)>,
+    pub wires: Vec<(PortRef, PortRef)>,
+    // The ports played on the left and right channels.
+    pub out: [PortRef; CHANNELS],
+    // The sample rate the components were built for.
+    pub rate: u32,
+}
+
+// One line of a patch file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Declare {
+        name: String,
+        kind: String,
+        args: Vec<String>,
+    },
+    Set {
+        port: PortRef,
+        value: i16,
+    },
+    Wire(PortRef, PortRef),
+    // The port to play on both channels, or the left and right ports.
+    Out(Vec<PortRef>),
+}
+
+impl Patch {
+    pub fn load(path: &Path, rate: u32) -> Result<Patch, PatchError> {
+        let text = fs::read_to_string(path).map_err(|e| PatchError::Io(path.to_path_buf(), e))?;
+        Patch::parse(&text, rate)
+    }
+
+    pub fn parse(text: &str, rate: u32) -> Result<Patch, PatchError> {
+        let ipc_64_map = ipc_64_map(rate);
+        let mut components: Vec<(String, AvailableComponents)> = vec![];
+        let mut wires = vec![];
+        let mut out = None;
+
+        for (i, line) in text.lines().enumerate() {
+            let line_no = i + 1;
+            let statement = match parse_line(line_no, line)? {
+                Some(statement) => statement,
+                None => continue,
+            };
+            match statement {
+                Statement::Out(ports) => {
+                    for (name, port) in ports.iter() {
+                        let c = find(&components, line_no, name)?;
+                        c.as_component()
+                            .output_slot(port)
+                            .map_err(|err| unknown_port(line_no, name, err))?;
+                    }
+                    let left = ports[0].clone();
+                    let right = ports.last().unwrap().clone();
+                    out = Some([left, right]);
+                }
+                Statement::Wire((src, src_port), (dst, dst_port)) => {
+                    let c = find(&components, line_no, &src)?;
+                    c.as_component()
+                        .output_slot(&src_port)
+                        .map_err(|err| unknown_port(line_no, &src, err))?;
+                    let c = find(&components, line_no, &dst)?;
+                    c.as_component()
+                        .input_slot(&dst_port)
+                        .map_err(|err| unknown_port(line_no, &dst, err))?;
+                    wires.push(((src, src_port), (dst, dst_port)));
+                }
+                Statement::Set {
+                    port: (name, port),
+                    value,
+                } => {
+                    let c = find_mut(&mut components, line_no, &name)?.as_component_mut();
+                    let slot = c
+                        .input_slot(&port)
+                        .map_err(|err| unknown_port(line_no, &name, err))?;
+                    let info = c.input_info()[slot];
+                    if !info.contains(value) {
+                        return Err(PatchError::OutOfRange {
+                            line: line_no,
+                            component: name,
+                            port: info,
+                            value,
+                        });
+                    }
+                    *c.input_mut(slot) = value;
+                }
+                Statement::Declare { name, kind, args } => {
+                    if components.iter().any(|x| x.0 == name) {
+                        return Err(PatchError::DuplicateName {
+                            line: line_no,
+                            name,
+                        });
+                    }
+                    let args = args.iter().map(|x| x.as_str()).collect::<Vec<&str>>();
+                    let c = build_component(line_no, &name, &kind, &args, rate, ipc_64_map)?;
+                    components.push((name, c));
+                }
+            }
+        }
+
+        Ok(Patch {
+            components,
+            wires,
+            out: out.ok_or(PatchError::MissingOutput)?,
+            rate,
+        })
+    }
+}
+
+// Parses a line of a patch file, which may be blank or a comment.
+pub fn parse_line(line_no: usize, line: &str) -> Result<Option<Statement>, PatchError> {
+    let line = match line.find('#') {
+        Some(j) => &line[..j],
+        None => line,
+    }
+    .trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(if let Some(rest) = line.strip_prefix("out ") {
+        let ports = rest
+            .split_whitespace()
+            .map(|x| port_ref(line_no, x))
+            .collect::<Result<Vec<PortRef>, PatchError>>()?;
+        if ports.len() > CHANNELS {
+            return Err(PatchError::Syntax {
+                line: line_no,
+                msg: format!(
+                    "`out` takes one port, or a left and a right port, found {}",
+                    ports.len()
+                ),
+            });
+        }
+        Statement::Out(ports)
+    } else if let Some(j) = line.find("->") {
+        Statement::Wire(
+            port_ref(line_no, line[..j].trim())?,
+            port_ref(line_no, line[j + 2..].trim())?,
+        )
+    } else if let Some(j) = line.find('=') {
+        let lhs = line[..j].trim();
+        let rhs = line[j + 1..].trim();
+        if lhs.contains('.') {
+            let port = port_ref(line_no, lhs)?;
+            let value = parse_i16(rhs).ok_or_else(|| PatchError::Syntax {
+                line: line_no,
+                msg: format!("`{}` is not a value between -32768 and 32767", rhs),
+            })?;
+            Statement::Set { port, value }
+        } else {
+            check_name(line_no, lhs)?;
+            let mut words = rhs.split_whitespace();
+            let kind = words.next().unwrap_or("").to_string();
+            let args = words.map(|x| x.to_string()).collect();
+            Statement::Declare {
+                name: lhs.to_string(),
+                kind,
+                args,
+            }
+        }
+    } else {
+        return Err(PatchError::Syntax {
+            line: line_no,
+            msg: format!("don't know what to do with `{}`", line),
+        });
+    }))
+}
+
+// A listing of the ports of every type of component, for --ports.
+pub fn describe_ports() -> String {
+    let ipc_64_map = ipc_64_map(util::DEFAULT_RATE);
+    let mut text = String::new();
+    for kind in COMPONENT_TYPES.iter() {
+        // Built without a source, so that listing ports doesn't open an
+        // input device.
+        let c = match *kind {
+            "AudioIn" => AvailableComponents::AudioIn(input::AudioIn::new(None)),
+            _ => build_component(0, kind, kind, &[], util::DEFAULT_RATE, ipc_64_map).unwrap(),
+        };
+        let c = c.as_component();
+        text += &format!("{}\n", kind);
+        for (dir, ports) in [("in", c.input_info()), ("out", c.output_info())] {
+            for p in ports.iter() {
+                text += &format!(
+                    "  {:3} {:17} {:8} {:7} {:>6} to {:<6} default {:<6} {}\n",
+                    dir,
+                    p.name,
+                    format!("{:?}", p.kind),
+                    p.unit,
+                    p.min,
+                    p.max,
+                    p.default,
+                    p.description
+                );
+            }
+        }
+    }
+    text
+}
+
+pub fn ipc_64_map(rate: u32) -> [u32; 256] {
+    let mut ipc_64_map = [0u32; 256];
+    for (cv, ipc_64) in ipc_64_map.iter_mut().enumerate().take(128) {
+        // At least 1, or WaveTableOsc never gets round its table at low
+        // rates.
+        *ipc_64 = util::cv_to_64th_wavetable_increment(cv as i16, rate)
+            .1
+            .max(1);
+    }
+    ipc_64_map
+}
+
+fn check_name(line: usize, name: &str) -> Result<(), PatchError> {
+    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return Err(PatchError::Syntax {
+            line,
+            msg: format!("`{}` is not a valid component name", name),
+        });
+    }
+    Ok(())
+}
+
+pub fn port_ref(line: usize, s: &str) -> Result<PortRef, PatchError> {
+    let mut parts = s.splitn(2, '.');
+    match (parts.next(), parts.next()) {
+        (Some(name), Some(port)) if !name.is_empty() && !port.is_empty() => {
+            Ok((name.trim().to_string(), port.trim().to_string()))
+        }
+        _ => Err(PatchError::Syntax {
+            line,
+            msg: format!("expected `component.port`, found `{}`", s),
+        }),
+    }
+}
+
+fn find<'a>(
+    components: &'a [(String, AvailableComponents)],
+    line: usize,
+    name: &str,
+) -> Result<&'a AvailableComponents, PatchError> {
+    components
+        .iter()
+        .find(|x| x.0 == name)
+        .map(|x| &x.1)
+        .ok_or_else(|| PatchError::UnknownComponent {
+            line,
+            name: name.to_string(),
+        })
+}
+
+fn find_mut<'a>(
+    components: &'a mut [(String, AvailableComponents)],
+    line: usize,
+    name: &str,
+) -> Result<&'a mut AvailableComponents, PatchError> {
+    components
+        .iter_mut()
+        .find(|x| x.0 == name)
+        .map(|x| &mut x.1)
+        .ok_or_else(|| PatchError::UnknownComponent {
+            line,
+            name: name.to_string(),
+        })
+}
+
+pub fn unknown_port(line: usize, component: &str, err: PortError) -> PatchError {
+    PatchError::UnknownPort {
+        line,
+        component: component.to_string(),
+        err,
+    }
+}
+
+// Accepts plain integers, as well as `max` and `min` for the ends of the
+// i16 range.
+fn parse_i16(s: &str) -> Option<i16> {
+    match s {
+        "max" => Some(i16::MAX),
+        "min" => Some(i16::MIN),
+        _ => s.parse().ok(),
+    }
+}
+
+pub fn build_component(
+    line: usize,
+    name: &str,
+    kind: &str,
+    args: &[&str],
+    rate: u32,
+    ipc_64_map: [u32; 256],
+) -> Result<AvailableComponents, PatchError> {
+    let bad_arg = |msg: String| PatchError::BadArgument {
+        line,
+        component: name.to_string(),
+        msg,
+    };
+
+    let mut kv = vec![];
+    for arg in args {
+        let mut parts = arg.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(k), Some(v)) => kv.push((k, v)),
+            _ => return Err(bad_arg(format!("expected `key=value`, found `{}`", arg))),
+        }
+    }
+    let allowed: &[&str] = match kind {
+        "WaveTableOsc" => &["table", "seed"],
+        "BasicArp" => &["scale", "octave"],
+        "BasicSeq" => &["beats", "beat_len", "clock"],
+        "BasicReverb" => &["delay"],
+        "Voices" => &["voices", "steal", "table", "seed"],
+        "AudioIn" => &["device", "file"],
+        _ => &[],
+    };
+    for (k, _) in kv.iter() {
+        if !allowed.contains(k) {
+            return Err(bad_arg(if allowed.is_empty() {
+                format!("{} takes no arguments, found `{}`", kind, k)
+            } else {
+                format!(
+                    "unknown argument `{}` for {} (expected one of: {})",
+                    k,
+                    kind,
+                    allowed.join(", ")
+                )
+            }));
+        }
+    }
+    let arg = |k: &str| kv.iter().find(|x| x.0 == k).map(|x| x.1);
+    let seed = match arg("seed") {
+        Some(_) if arg("table") != Some("white_noise") => {
+            return Err(bad_arg(
+                "`seed` only applies to table=white_noise".to_string(),
+            ))
+        }
+        Some(v) => Some(
+            v.parse::<u64>()
+                .map_err(|_| bad_arg(format!("`{}` is not a seed", v)))?,
+        ),
+        None => None,
+    };
+
+    Ok(match kind {
+        "Adsr" => AvailableComponents::Adsr(env::Adsr::new()),
+        "BasicArp" => {
+            let mut arp = arp::BasicArp::new();
+            if let Some(v) = arg("scale") {
+                let root: arp::TtetNote = v.parse().map_err(bad_arg)?;
+                arp.notes = root.major_scale();
+            }
+            if let Some(v) = arg("octave") {
+                arp.octave = v
+                    .parse()
+                    .map_err(|_| bad_arg(format!("`{}` is not an octave", v)))?;
+            }
+            AvailableComponents::BasicArp(arp)
+        }
+        "BasicSeq" => {
+            let mut beats = [false; 16];
+            if let Some(v) = arg("beats") {
+                for b in v.split(',') {
+                    match b.parse::<usize>() {
+                        Ok(i) if i < beats.len() => beats[i] = true,
+                        _ => return Err(bad_arg(format!("`{}` is not a step from 0 to 15", b))),
+                    }
+                }
+            }
+            let beat_len = match arg("beat_len") {
+                Some(v) => {
+                    parse_i16(v).ok_or_else(|| bad_arg(format!("`{}` is not a beat length", v)))?
+                }
+                None => 128,
+            };
+            let wired = match arg("clock") {
+                None | Some("transport") => false,
+                Some("wire") => true,
+                Some(v) => {
+                    return Err(bad_arg(format!(
+                        "expected clock=transport or clock=wire, found `{}`",
+                        v
+                    )))
+                }
+            };
+            AvailableComponents::BasicSeq(seq::BasicSeq::new(
+                Arc::new(seq::Pattern::new(beats)),
+                [beat_len; 16],
+                wired,
+            ))
+        }
+        "AudioIn" => {
+            let source = match (arg("device"), arg("file")) {
+                (Some(_), Some(_)) => {
+                    return Err(bad_arg(
+                        "expected either `device` or `file`, not both".to_string(),
+                    ))
+                }
+                (None, Some(file)) => {
+                    input::load_file(&PathBuf::from(file), rate).map(input::Source::File)
+                }
+                (device, None) => input::open_device(device, rate).map(input::Source::Device),
+            };
+            AvailableComponents::AudioIn(input::AudioIn::new(Some(source.map_err(bad_arg)?)))
+        }
+        "Clock" => AvailableComponents::Clock(seq::Clock::new(rate)),
+        "Mixer" => AvailableComponents::Mixer(mix::Mixer::new()),
+        "Pan" => AvailableComponents::Pan(mix::Pan::new()),
+        "Vca" => AvailableComponents::Vca(amp::Vca::new(i16::MAX)),
+        "WaveTableOsc" => {
+            let table = arg("table").unwrap_or("sin");
+            AvailableComponents::WaveTableOsc(Box::new(
+                wave_table(table, seed, ipc_64_map).map_err(bad_arg)?,
+            ))
+        }
+        "BasicReverb" => {
+            let mut rvb = rvb::BasicReverb::new(rate);
+            if let Some(v) = arg("delay") {
+                let delays = v.split(',').collect::<Vec<&str>>();
+                if delays.len() != rvb.delay.len() {
+                    return Err(bad_arg(format!(
+                        "expected {} delays, found {}",
+                        rvb.delay.len(),
+                        delays.len()
+                    )));
+                }
+                // In ms, so that the reverb sounds the same at any rate.
+                for (d, s) in rvb.delay.iter_mut().zip(delays) {
+                    *d = match s.parse::<f64>() {
+                        Ok(ms) if ms > 0. && ms <= 1000. => {
+                            ((ms * rate as f64 / 1000.).round() as usize).max(1)
+                        }
+                        Ok(ms) if ms > 1000. => {
+                            return Err(bad_arg(format!(
+                                "a delay of {} ms is longer than a second",
+                                ms
+                            )))
+                        }
+                        _ => return Err(bad_arg(format!("`{}` is not a delay in ms", s))),
+                    };
+                }
+            }
+            AvailableComponents::BasicReverb(rvb)
+        }
+        "Voices" => {
+            let voices = match arg("voices") {
+                Some(v) => match v.parse::<usize>() {
+                    Ok(n) if (1..=poly::MAX_VOICES).contains(&n) => n,
+                    _ => {
+                        return Err(bad_arg(format!(
+                            "`{}` is not a number of voices from 1 to {}",
+                            v,
+                            poly::MAX_VOICES
+                        )))
+                    }
+                },
+                None => 4,
+            };
+            let steal = match arg("steal") {
+                Some(v) => v.parse().map_err(bad_arg)?,
+                None => poly::Steal::Oldest,
+            };
+            let table = arg("table").unwrap_or("sin");
+            let oscs = (0..voices)
+                .map(|_| wave_table(table, seed, ipc_64_map))
+                .collect::<Result<Vec<osc::WaveTableOsc>, String>>()
+                .map_err(bad_arg)?;
+            AvailableComponents::Voices(poly::Voices::new(oscs, steal))
+        }
+        _ => {
+            return Err(PatchError::UnknownComponentType {
+                line,
+                name: name.to_string(),
+                kind: kind.to_string(),
+                valid: COMPONENT_TYPES.to_vec(),
+            })
+        }
+    })
+}
+
+fn wave_table(
+    table: &str,
+    seed: Option<u64>,
+    ipc_64_map: [u32; 256],
+) -> Result<osc::WaveTableOsc, String> {
+    Ok(match table {
+        "sin" => osc::WaveTableOsc::sin(ipc_64_map, 69),
+        "saw" => osc::WaveTableOsc::saw(ipc_64_map, 69),
+        "triangle" => osc::WaveTableOsc::triangle(ipc_64_map, 69),
+        "square" => osc::WaveTableOsc::square(ipc_64_map, 69),
+        "white_noise" => match seed {
+            Some(seed) => osc::WaveTableOsc::seeded_white_noise(ipc_64_map, 69, seed),
+            None => osc::WaveTableOsc::white_noise(ipc_64_map, 69),
+        },
+        t => {
+            return Err(format!(
+                "unknown table `{}` (expected one of: sin, saw, triangle, square, white_noise)",
+                t
+            ))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+
+    const RATE: u32 = util::DEFAULT_RATE;
+
+    fn parse_err(text: &str) -> PatchError {
+        match Patch::parse(text, RATE) {
+            Ok(_) => panic!("parsed:\n{}", text),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn parses_components_wires_and_output() {
+        let patch = Patch::parse(
+            "# a comment
+             wto1 = WaveTableOsc table=saw  # another
+             vca1 = Vca
+             vca1.amp_cv = max
+
+             wto1.out -> vca1.in_cv
+             out vca1.out",
+            RATE,
+        )
+        .unwrap();
+        let names = patch.components.iter().map(|x| x.0.as_str());
+        assert_eq!(names.collect::<Vec<&str>>(), ["wto1", "vca1"]);
+        let port = |c: &str, p: &str| (c.to_string(), p.to_string());
+        assert_eq!(patch.wires, [(port("wto1", "out"), port("vca1", "in_cv"))]);
+        assert_eq!(patch.out, [port("vca1", "out"), port("vca1", "out")]);
+    }
+
+    #[test]
+    fn errors_give_the_line() {
+        let err = parse_err("wto1 = WaveTableOsc\n\nwto1 = Vca\nout wto1.out");
+        assert!(
+            matches!(err, PatchError::DuplicateName { line: 3, .. }),
+            "{}",
+            err
+        );
+        assert_eq!(err.to_string(), "line 3: `wto1` is already declared");
+
+        let err = parse_err("wto1 = WaveTableOsc\nwto1.out -> vca1.in_cv");
+        assert!(
+            matches!(err, PatchError::UnknownComponent { line: 2, .. }),
+            "{}",
+            err
+        );
+
+        let err = parse_err("wto1 = WaveTableOsc\n# nothing\nout wto1.nope");
+        assert!(
+            matches!(err, PatchError::UnknownPort { line: 3, .. }),
+            "{}",
+            err
+        );
+
+        let err = parse_err("wto1 = Theremin");
+        assert!(
+            matches!(err, PatchError::UnknownComponentType { line: 1, .. }),
+            "{}",
+            err
+        );
+
+        let err = parse_err("wto1 = WaveTableOsc table=sine");
+        assert!(
+            matches!(err, PatchError::BadArgument { line: 1, .. }),
+            "{}",
+            err
+        );
+
+        let err = parse_err("wto1 = WaveTableOsc\nwto1.freq = 200");
+        assert!(
+            matches!(
+                err,
+                PatchError::OutOfRange {
+                    line: 2,
+                    value: 200,
+                    ..
+                }
+            ),
+            "{}",
+            err
+        );
+
+        let err = parse_err("wto1 = WaveTableOsc\nwto1.freq = loud");
+        assert!(matches!(err, PatchError::Syntax { line: 2, .. }), "{}", err);
+
+        let err = parse_err("wto1 = WaveTableOsc\nwto1 out");
+        assert!(matches!(err, PatchError::Syntax { line: 2, .. }), "{}", err);
+
+        let err = parse_err("wto1 = WaveTableOsc");
+        assert!(matches!(err, PatchError::MissingOutput), "{}", err);
+    }
+
+    #[test]
+    fn every_port_is_described() {
+        let listing = describe_ports();
+        for kind in COMPONENT_TYPES {
+            assert!(
+                listing.contains(&format!("{}\n", kind)),
+                "{} isn't listed",
+                kind
+            );
+            let c = match kind {
+                "AudioIn" => AvailableComponents::AudioIn(input::AudioIn::new(None)),
+                _ => build_component(1, kind, kind, &[], RATE, ipc_64_map(RATE)).unwrap(),
+            };
+            let c = c.as_component();
+            for ports in [c.input_info(), c.output_info()] {
+                for (slot, p) in ports.iter().enumerate() {
+                    assert!(p.min <= p.max, "{}.{}", kind, p.name);
+                    assert!(p.contains(p.default), "{}.{}", kind, p.name);
+                    assert!(!p.description.is_empty(), "{}.{}", kind, p.name);
+                    let first = ports.iter().position(|x| x.name == p.name);
+                    assert_eq!(first, Some(slot), "{}.{} twice", kind, p.name);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn reverb_delays_default_from_the_rate() {
+        let patch = Patch::parse("rvb1 = BasicReverb\nout rvb1.out", RATE).unwrap();
+        match &patch.components[0].1 {
+            AvailableComponents::BasicReverb(rvb) => {
+                assert_eq!(rvb.delay, [14700, 8820, 5512]);
+            }
+            _ => unreachable!(),
+        }
+        let mut engine = Engine::new(patch, 120);
+        let mut out = vec![0; 1024 * CHANNELS];
+        engine.render(&mut out);
+    }
+
+    #[test]
+    fn reverb_delays_are_in_ms() {
+        for (rate, delay) in [
+            (48_000, [16_000, 9_600, 6_000]),
+            (96_000, [32_000, 19_200, 12_000]),
+        ] {
+            let text = "rvb1 = BasicReverb delay=333.33,200,125\nout rvb1.out";
+            match &Patch::parse(text, rate).unwrap().components[0].1 {
+                AvailableComponents::BasicReverb(rvb) => assert_eq!(rvb.delay, delay),
+                _ => unreachable!(),
+            }
+        }
+        let err = parse_err("rvb1 = BasicReverb delay=1001,20,11\nout rvb1.out");
+        assert!(
+            matches!(err, PatchError::BadArgument { line: 1, .. }),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn reverb_rejects_a_zero_delay() {
+        let err = parse_err("rvb1 = BasicReverb delay=0,20,11\nout rvb1.out");
+        assert!(
+            matches!(err, PatchError::BadArgument { line: 1, .. }),
+            "{}",
+            err
+        );
+    }
+}
@@ -0,0 +1,327 @@
+use std::sync::Arc;
+
+use rtrb::Consumer;
+use rtrb::Producer;
+
+use crate::audio::AvailableComponents;
+use crate::engine::inputs_of;
+use crate::engine::Graph;
+use crate::engine::Layout;
+use crate::engine::Node;
+use crate::engine::Update;
+use crate::engine::CHANNELS;
+use crate::patch;
+use crate::patch::PatchError;
+use crate::patch::PortRef;
+use crate::seq::Pattern;
+use crate::util::port_slot;
+
+// Changes a patch while it plays. The Patcher keeps its own copy of the
+// names, ports and wires of the patch, builds a new Graph for each change
+// and hands it to the audio thread, which swaps it in between blocks.
+// Errors have line 0, since they don't come from a file.
+//
+// It also keeps the patterns of the sequencers, so that steps can be
+// turned on and off without going through the audio thread.
+pub struct Patcher {
+    layout: Vec<Layout>,
+    wires: Vec<(PortRef, PortRef)>,
+    out: [PortRef; CHANNELS],
+    delayed: Vec<(PortRef, PortRef)>,
+    updates: Producer<Box<Update>>,
+    garbage: Consumer<Box<Update>>,
+    rate: u32,
+    ipc_64_map: [u32; 256],
+    patterns: Vec<(String, Arc<Pattern>)>,
+}
+
+impl Patcher {
+    pub fn new(
+        layout: Vec<Layout>,
+        wires: Vec<(PortRef, PortRef)>,
+        out: [PortRef; CHANNELS],
+        delayed: Vec<(PortRef, PortRef)>,
+        updates: Producer<Box<Update>>,
+        garbage: Consumer<Box<Update>>,
+        rate: u32,
+    ) -> Patcher {
+        Patcher {
+            layout,
+            wires,
+            out,
+            delayed,
+            updates,
+            garbage,
+            rate,
+            ipc_64_map: patch::ipc_64_map(rate),
+            patterns: vec![],
+        }
+    }
+
+    pub fn delayed_wires(&self) -> Vec<(PortRef, PortRef)> {
+        self.delayed.clone()
+    }
+
+    // The names of the components, in the order the engine will have them
+    // once it has swapped in the last change.
+    pub fn names(&self) -> Vec<String> {
+        self.layout.iter().map(|x| x.name.clone()).collect()
+    }
+
+    // The pattern of a BasicSeq.
+    pub fn pattern(&self, name: &str) -> Option<&Pattern> {
+        self.patterns.iter().find(|x| x.0 == name).map(|x| &*x.1)
+    }
+
+    // Keeps the pattern of a component, if it has one.
+    pub fn keep_pattern(&mut self, name: &str, c: &AvailableComponents) {
+        if let AvailableComponents::BasicSeq(seq) = c {
+            self.patterns
+                .push((name.to_string(), Arc::clone(&seq.pattern)));
+        }
+    }
+
+    // Adds a component, as if declared with `name = kind args` in a patch
+    // file, and returns the values of its inputs.
+    pub fn add(
+        &mut self,
+        name: &str,
+        kind: &str,
+        args: &[&str],
+    ) -> Result<Vec<(PortRef, i16)>, PatchError> {
+        if self.index_of(name).is_ok() {
+            return Err(PatchError::DuplicateName {
+                line: 0,
+                name: name.to_string(),
+            });
+        }
+        let mut c = patch::build_component(0, name, kind, args, self.rate, self.ipc_64_map)?;
+        let params = inputs_of(name, c.as_component_mut());
+        self.keep_pattern(name, &c);
+
+        let mut layout = self.layout.clone();
+        layout.push(Layout::of(name, c.as_component()));
+        let mut nodes = empty_nodes(layout.len());
+        nodes[layout.len() - 1] = Some(Box::new((name.to_string(), c)));
+        let moves = (0..self.layout.len()).map(|i| (i, i)).collect();
+        if let Err(e) = self.send(layout, self.wires.clone(), nodes, moves) {
+            self.patterns.retain(|x| x.0 != name);
+            return Err(e);
+        }
+        Ok(params)
+    }
+
+    // Removes a component, along with the wires to and from it.
+    pub fn remove(&mut self, name: &str) -> Result<(), PatchError> {
+        let i = self.index_of(name)?;
+        if self.out.iter().any(|x| x.0 == name) {
+            return Err(PatchError::RemovingOutput(name.to_string()));
+        }
+
+        let mut layout = self.layout.clone();
+        layout.remove(i);
+        let wires = self
+            .wires
+            .iter()
+            .filter(|(src, dst)| src.0 != name && dst.0 != name)
+            .cloned()
+            .collect();
+        let moves = (0..self.layout.len())
+            .filter(|j| *j != i)
+            .map(|j| (j, if j < i { j } else { j - 1 }))
+            .collect();
+        self.send(layout, wires, empty_nodes(self.layout.len() - 1), moves)?;
+        self.patterns.retain(|x| x.0 != name);
+        Ok(())
+    }
+
+    pub fn connect(&mut self, src: PortRef, dst: PortRef) -> Result<(), PatchError> {
+        self.check(&src, "output")?;
+        self.check(&dst, "input")?;
+        if self.wires.contains(&(src.clone(), dst.clone())) {
+            return Err(PatchError::AlreadyWired(src, dst));
+        }
+
+        let mut wires = self.wires.clone();
+        wires.push((src, dst));
+        let moves = (0..self.layout.len()).map(|i| (i, i)).collect();
+        self.send(
+            self.layout.clone(),
+            wires,
+            empty_nodes(self.layout.len()),
+            moves,
+        )
+    }
+
+    pub fn disconnect(&mut self, src: PortRef, dst: PortRef) -> Result<(), PatchError> {
+        let w = match self
+            .wires
+            .iter()
+            .position(|x| *x == (src.clone(), dst.clone()))
+        {
+            Some(w) => w,
+            None => return Err(PatchError::NotWired(src, dst)),
+        };
+
+        let mut wires = self.wires.clone();
+        wires.remove(w);
+        let moves = (0..self.layout.len()).map(|i| (i, i)).collect();
+        self.send(
+            self.layout.clone(),
+            wires,
+            empty_nodes(self.layout.len()),
+            moves,
+        )
+    }
+
+    // Whether `port` is an output of a component of the patch.
+    pub fn check_output(&self, port: &PortRef) -> Result<(), PatchError> {
+        self.check(port, "output")
+    }
+
+    // Drops the graphs and components that the audio thread is done with.
+    pub fn collect_garbage(&mut self) {
+        while self.garbage.pop().is_ok() {}
+    }
+
+    fn send(
+        &mut self,
+        layout: Vec<Layout>,
+        wires: Vec<(PortRef, PortRef)>,
+        nodes: Vec<Option<Node>>,
+        moves: Vec<(usize, usize)>,
+    ) -> Result<(), PatchError> {
+        self.collect_garbage();
+        if self.updates.slots() == 0 {
+            return Err(PatchError::Busy);
+        }
+
+        let graph = Graph::new(&layout, wires.clone(), &self.out);
+        let delayed = graph.delayed_wires();
+        let update = Update {
+            graph,
+            nodes,
+            moves,
+        };
+        if self.updates.push(Box::new(update)).is_err() {
+            return Err(PatchError::Busy);
+        }
+
+        self.layout = layout;
+        self.wires = wires;
+        self.delayed = delayed;
+        Ok(())
+    }
+
+    fn index_of(&self, name: &str) -> Result<usize, PatchError> {
+        self.layout
+            .iter()
+            .position(|x| x.name == name)
+            .ok_or_else(|| PatchError::UnknownComponent {
+                line: 0,
+                name: name.to_string(),
+            })
+    }
+
+    fn check(&self, (name, port): &PortRef, dir: &'static str) -> Result<(), PatchError> {
+        let c = &self.layout[self.index_of(name)?];
+        let ports = if dir == "input" { c.inputs } else { c.outputs };
+        port_slot(dir, ports, port).map_err(|err| patch::unknown_port(0, name, err))?;
+        Ok(())
+    }
+}
+
+fn empty_nodes(len: usize) -> Vec<Option<Node>> {
+    (0..len).map(|_| None).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::patch::Patch;
+    use crate::util::DEFAULT_RATE;
+
+    fn port(component: &str, port: &str) -> PortRef {
+        (component.to_string(), port.to_string())
+    }
+
+    fn engine() -> Engine {
+        let patch = "wto1 = WaveTableOsc
+                     vca1 = Vca
+                     vca1.amp_cv = 0
+                     wto1.out -> vca1.in_cv
+                     out vca1.out";
+        Engine::new(Patch::parse(patch, DEFAULT_RATE).unwrap(), 120)
+    }
+
+    #[test]
+    fn changes_are_swapped_in_between_blocks() {
+        let mut engine = engine();
+        let mut patcher = engine.patcher();
+        let mut out = vec![0; 64 * CHANNELS];
+
+        let params = patcher.add("mix1", "Mixer", &[]).unwrap();
+        assert_eq!(params[0], (port("mix1", "a"), 0));
+        patcher
+            .connect(port("vca1", "out"), port("mix1", "a"))
+            .unwrap();
+        patcher
+            .connect(port("mix1", "out"), port("vca1", "amp_cv"))
+            .unwrap();
+        assert_eq!(patcher.names(), ["wto1", "vca1", "mix1"]);
+        assert_eq!(
+            patcher.delayed_wires(),
+            [(port("mix1", "out"), port("vca1", "amp_cv"))]
+        );
+        assert!(engine.component("mix1").is_none());
+
+        assert!(engine.swap_graph());
+        assert!(!engine.swap_graph());
+        engine.render(&mut out);
+        assert!(engine.component("mix1").is_some());
+        assert_eq!(engine.delayed_wires(), patcher.delayed_wires());
+
+        patcher
+            .disconnect(port("mix1", "out"), port("vca1", "amp_cv"))
+            .unwrap();
+        patcher.remove("wto1").unwrap();
+        assert!(engine.swap_graph());
+        engine.render(&mut out);
+        assert!(engine.component("wto1").is_none());
+        assert!(engine.delayed_wires().is_empty());
+        // Components that are kept keep their place, less the removed one.
+        assert_eq!(engine.output_slot("mix1", "out"), Some((1, 0)));
+        patcher.collect_garbage();
+    }
+
+    #[test]
+    fn bad_changes_are_errors() {
+        let mut engine = engine();
+        let mut patcher = engine.patcher();
+        let wire = (port("wto1", "out"), port("vca1", "in_cv"));
+
+        let err = patcher.add("wto1", "Vca", &[]).unwrap_err();
+        assert!(matches!(err, PatchError::DuplicateName { line: 0, .. }));
+        let err = patcher.add("wto2", "Theremin", &[]).unwrap_err();
+        assert!(matches!(err, PatchError::UnknownComponentType { .. }));
+        let err = patcher.connect(wire.0.clone(), wire.1.clone()).unwrap_err();
+        assert_eq!(err.to_string(), "`wto1.out -> vca1.in_cv` is already wired");
+        let err = patcher
+            .connect(port("vca1", "in_cv"), port("wto1", "freq"))
+            .unwrap_err();
+        assert!(matches!(err, PatchError::UnknownPort { line: 0, .. }));
+        let err = patcher
+            .disconnect(port("vca1", "out"), port("wto1", "freq"))
+            .unwrap_err();
+        assert!(matches!(err, PatchError::NotWired(..)));
+        let err = patcher.remove("vca1").unwrap_err();
+        assert!(matches!(err, PatchError::RemovingOutput(_)));
+        let err = patcher.remove("vca2").unwrap_err();
+        assert!(matches!(err, PatchError::UnknownComponent { line: 0, .. }));
+
+        // None of which changed anything.
+        assert!(!engine.swap_graph());
+        assert_eq!(patcher.names(), ["wto1", "vca1"]);
+    }
+}
@@ -0,0 +1,5 @@
+mod voices;
+
+pub use voices::Steal;
+pub use voices::Voices;
+pub use voices::MAX_VOICES;
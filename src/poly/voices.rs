@@ -0,0 +1,433 @@
+use std::str::FromStr;
+
+use crate::amp::Vca;
+use crate::env::Adsr;
+use crate::osc::WaveTableOsc;
+use crate::util::Component;
+use crate::util::PortInfo;
+use crate::util::PortKind;
+
+const INPUTS: [PortInfo; 8] = [
+    PortInfo {
+        name: "note",
+        kind: PortKind::Pitch,
+        unit: "note",
+        min: 0,
+        max: 127,
+        default: 69,
+        description: "The note to start on the next trigger, e.g. from a BasicArp.",
+    },
+    PortInfo {
+        name: "trigger",
+        kind: PortKind::Trigger,
+        unit: "",
+        min: 0,
+        max: i16::MAX,
+        default: 0,
+        description: "Starts `note` on a free voice, or steals one, on a rising edge.",
+    },
+    PortInfo {
+        name: "gate",
+        kind: PortKind::Gate,
+        unit: "",
+        min: 0,
+        max: i16::MAX,
+        default: 0,
+        description: "Releases the notes started by trigger when it goes low.",
+    },
+    PortInfo {
+        name: "attack_for",
+        kind: PortKind::Cv,
+        unit: "samples",
+        min: 0,
+        max: i16::MAX,
+        default: 2048,
+        description: "The attack of every voice's envelope.",
+    },
+    PortInfo {
+        name: "attack_to",
+        kind: PortKind::Cv,
+        unit: "level",
+        min: 0,
+        max: i16::MAX,
+        default: 8191,
+        description: "The peak of every voice's envelope.",
+    },
+    PortInfo {
+        name: "decay_for",
+        kind: PortKind::Cv,
+        unit: "samples",
+        min: 0,
+        max: i16::MAX,
+        default: 1024,
+        description: "The decay of every voice's envelope.",
+    },
+    PortInfo {
+        name: "sustain_at",
+        kind: PortKind::Cv,
+        unit: "level",
+        min: 0,
+        max: i16::MAX,
+        default: 4095,
+        description: "The sustain level of every voice's envelope.",
+    },
+    PortInfo {
+        name: "release_for",
+        kind: PortKind::Cv,
+        unit: "samples",
+        min: 0,
+        max: i16::MAX,
+        default: 4096,
+        description: "The release of every voice's envelope.",
+    },
+];
+
+const OUTPUTS: [PortInfo; 2] = [
+    PortInfo {
+        name: "out",
+        kind: PortKind::Audio,
+        unit: "",
+        min: i16::MIN,
+        max: i16::MAX,
+        default: 0,
+        description: "The sum of the voices, saturated.",
+    },
+    PortInfo {
+        name: "active",
+        kind: PortKind::Cv,
+        unit: "voices",
+        min: 0,
+        max: MAX_VOICES as i16,
+        default: 0,
+        description: "How many voices are sounding.",
+    },
+];
+
+pub const MAX_VOICES: usize = 16;
+
+// Which voice a new note takes when they're all sounding.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Steal {
+    Oldest,
+    Quietest,
+    // Retrigger the voice already playing the note, if there is one, even
+    // when other voices are free. Otherwise, the oldest.
+    SameNote,
+}
+
+impl FromStr for Steal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "oldest" => Ok(Steal::Oldest),
+            "quietest" => Ok(Steal::Quietest),
+            "same_note" => Ok(Steal::SameNote),
+            _ => Err(format!(
+                "unknown voice stealing `{}` (expected one of: oldest, quietest, same_note)",
+                s
+            )),
+        }
+    }
+}
+
+// What started a note, and so what releases it.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Source {
+    Gate,
+    Keys,
+}
+
+// One copy of the voice sub-patch:
+//   osc.out -> vca.in_cv
+//   env.out -> vca.amp_cv
+struct Voice {
+    osc: WaveTableOsc,
+    env: Adsr,
+    vca: Vca,
+    note: i16,
+    held: Option<Source>,
+    // The sample the note started on.
+    started: u64,
+}
+
+impl Voice {
+    // A voice that's been started is sounding even before its envelope
+    // has seen the trigger.
+    fn sounding(&self) -> bool {
+        self.held.is_some() || !self.env.is_off()
+    }
+}
+
+// Plays notes on a number of voices at once, and sums them.
+//
+// Every voice is the same chain, a WaveTableOsc through a Vca with an
+// Adsr on its amp: only the table, and the envelope's settings, can be
+// changed, not the chain itself.
+pub struct Voices {
+    voices: Vec<Voice>,
+    pub steal: Steal,
+    note: i16,
+    trigger: i16,
+    gate: i16,
+    attack_for: i16,
+    attack_to: i16,
+    decay_for: i16,
+    sustain_at: i16,
+    release_for: i16,
+    prev_trigger: i16,
+    prev_gate: i16,
+    out: i16,
+    active: i16,
+    counter: u64,
+}
+
+impl Voices {
+    // One voice is made for each oscillator.
+    pub fn new(oscs: Vec<WaveTableOsc>, steal: Steal) -> Voices {
+        Voices {
+            voices: oscs
+                .into_iter()
+                .take(MAX_VOICES)
+                .map(|osc| Voice {
+                    osc,
+                    env: Adsr::new(),
+                    vca: Vca::new(0),
+                    note: 0,
+                    held: None,
+                    started: 0,
+                })
+                .collect(),
+            steal,
+            note: INPUTS[0].default,
+            trigger: 0,
+            gate: 0,
+            attack_for: INPUTS[3].default,
+            attack_to: INPUTS[4].default,
+            decay_for: INPUTS[5].default,
+            sustain_at: INPUTS[6].default,
+            release_for: INPUTS[7].default,
+            prev_trigger: 0,
+            prev_gate: 0,
+            out: 0,
+            active: 0,
+            counter: 0,
+        }
+    }
+
+    // Notes from the keyboard, rather than the trigger and gate inputs.
+    pub fn note_on(&mut self, note: i16) {
+        self.start(note, Source::Keys);
+    }
+
+    pub fn note_off(&mut self, note: i16) {
+        for v in self.voices.iter_mut() {
+            if v.note == note && v.held == Some(Source::Keys) {
+                v.held = None;
+                v.env.gate = 0;
+            }
+        }
+    }
+
+    fn start(&mut self, note: i16, source: Source) {
+        // Notes past the end of the increment map would stall the
+        // oscillator.
+        let note = note.clamp(INPUTS[0].min, INPUTS[0].max);
+        let i = match self.allocate(note) {
+            Some(i) => i,
+            None => return,
+        };
+        let v = &mut self.voices[i];
+        // A voice that's stolen starts again from the attack.
+        v.env.reset();
+        v.env.trigger = i16::MAX;
+        v.env.gate = i16::MAX;
+        v.note = note;
+        v.held = Some(source);
+        v.started = self.counter;
+    }
+
+    fn allocate(&self, note: i16) -> Option<usize> {
+        let voices = &self.voices;
+        if self.steal == Steal::SameNote {
+            if let Some(i) = voices.iter().position(|v| v.sounding() && v.note == note) {
+                return Some(i);
+            }
+        }
+        if let Some(i) = voices.iter().position(|v| !v.sounding()) {
+            return Some(i);
+        }
+        let stolen = match self.steal {
+            Steal::Quietest => voices
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, v)| v.env.output(0).saturating_abs()),
+            Steal::Oldest | Steal::SameNote => {
+                voices.iter().enumerate().min_by_key(|(_, v)| v.started)
+            }
+        };
+        stolen.map(|(i, _)| i)
+    }
+}
+
+impl Component for Voices {
+    fn tick(&mut self, _velocity: i16) {}
+    fn step(&mut self) {
+        let q = i16::MAX / 4;
+        let tq = 3 * q;
+        if self.trigger > tq && self.prev_trigger < q {
+            self.start(self.note, Source::Gate);
+        }
+        if self.gate < q && self.prev_gate >= q {
+            for v in self.voices.iter_mut() {
+                if v.held == Some(Source::Gate) {
+                    v.held = None;
+                    v.env.gate = 0;
+                }
+            }
+        }
+        self.prev_trigger = self.trigger;
+        self.prev_gate = self.gate;
+
+        let mut out = 0i16;
+        let mut active = 0;
+        for v in self.voices.iter_mut() {
+            if !v.sounding() {
+                continue;
+            }
+            v.env.attack_for = self.attack_for;
+            v.env.attack_to = self.attack_to;
+            v.env.decay_for = self.decay_for;
+            v.env.sustain_at = self.sustain_at;
+            v.env.release_for = self.release_for;
+            v.env.step();
+            // The trigger is a one sample pulse.
+            v.env.trigger = 0;
+
+            v.osc.freq = v.note;
+            v.osc.step();
+
+            v.vca.amp_cv = v.env.output(0);
+            v.vca.in_cv = v.osc.output(0);
+            v.vca.step();
+
+            out = out.saturating_add(v.vca.output(0));
+            active += 1;
+        }
+        self.out = out;
+        self.active = active;
+        self.counter += 1;
+    }
+
+    fn input_info(&self) -> &'static [PortInfo] {
+        &INPUTS
+    }
+
+    fn output_info(&self) -> &'static [PortInfo] {
+        &OUTPUTS
+    }
+
+    fn input_mut(&mut self, slot: usize) -> &mut i16 {
+        match slot {
+            0 => &mut self.note,
+            1 => &mut self.trigger,
+            2 => &mut self.gate,
+            3 => &mut self.attack_for,
+            4 => &mut self.attack_to,
+            5 => &mut self.decay_for,
+            6 => &mut self.sustain_at,
+            7 => &mut self.release_for,
+            _ => panic!("Voices has no input slot {}", slot),
+        }
+    }
+
+    fn output(&self, slot: usize) -> i16 {
+        match slot {
+            0 => self.out,
+            1 => self.active,
+            _ => panic!("Voices has no output slot {}", slot),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::patch;
+    use crate::util::DEFAULT_RATE;
+
+    fn voices(n: usize, steal: Steal) -> Voices {
+        let ipc_64_map = patch::ipc_64_map(DEFAULT_RATE);
+        let oscs = (0..n).map(|_| WaveTableOsc::sin(ipc_64_map, 69)).collect();
+        let mut voices = Voices::new(oscs, steal);
+        voices.attack_for = 1000;
+        voices
+    }
+
+    fn play(voices: &mut Voices, note: i16, steps: usize) {
+        voices.note_on(note);
+        for _ in 0..steps {
+            voices.step();
+        }
+    }
+
+    fn notes(voices: &Voices) -> Vec<i16> {
+        voices.voices.iter().map(|v| v.note).collect()
+    }
+
+    #[test]
+    fn free_voices_are_used_first() {
+        let mut v = voices(3, Steal::Oldest);
+        play(&mut v, 60, 10);
+        play(&mut v, 62, 10);
+        assert_eq!(notes(&v), [60, 62, 0]);
+        assert_eq!(v.output(1), 2);
+
+        // Once its release is over, a voice is free again.
+        v.note_off(60);
+        play(&mut v, 64, 0);
+        assert_eq!(notes(&v), [60, 62, 64]);
+        for _ in 0..INPUTS[7].default + 10 {
+            v.step();
+        }
+        assert_eq!(v.output(1), 2);
+        play(&mut v, 65, 0);
+        assert_eq!(notes(&v), [65, 62, 64]);
+    }
+
+    #[test]
+    fn oldest_steals_the_first_started() {
+        let mut v = voices(2, Steal::Oldest);
+        play(&mut v, 60, 500);
+        play(&mut v, 62, 10);
+        play(&mut v, 64, 10);
+        assert_eq!(notes(&v), [64, 62]);
+        play(&mut v, 60, 10);
+        assert_eq!(notes(&v), [64, 60]);
+    }
+
+    #[test]
+    fn quietest_steals_the_lowest_envelope() {
+        // The first voice is half way through its attack, the second has
+        // only just started.
+        let mut v = voices(2, Steal::Quietest);
+        play(&mut v, 60, 500);
+        play(&mut v, 62, 10);
+        play(&mut v, 64, 10);
+        assert_eq!(notes(&v), [60, 64]);
+    }
+
+    #[test]
+    fn same_note_restarts_its_voice() {
+        let mut v = voices(2, Steal::SameNote);
+        play(&mut v, 60, 10);
+        play(&mut v, 62, 10);
+        play(&mut v, 60, 10);
+        assert_eq!(notes(&v), [60, 62]);
+        assert_eq!(v.output(1), 2);
+        // The restarted voice is now the newer one, so a new note takes
+        // the other.
+        play(&mut v, 64, 10);
+        assert_eq!(notes(&v), [60, 64]);
+    }
+}
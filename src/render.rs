@@ -0,0 +1,72 @@
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::path::Path;
+
+use wav::bit_depth::BitDepth;
+use wav::header::Header;
+use wav::header::WAV_FORMAT_PCM;
+
+use crate::engine::Engine;
+use crate::engine::CHANNELS;
+use crate::out::Readings;
+
+// How much of a patch to render.
+pub enum Length {
+    Bars(usize),
+    Seconds(f64),
+}
+
+// Runs a patch without a sound device or the TUI, as fast as it will go,
+// and returns its output, as interleaved frames, and the levels over the
+// whole of it.
+pub fn render(mut engine: Engine, length: Length) -> (Vec<i16>, Readings) {
+    let len = match length {
+        Length::Bars(bars) => bars * engine.bar_len(),
+        Length::Seconds(secs) => (secs * engine.rate() as f64) as usize,
+    };
+    let mut out = vec![0; len * CHANNELS];
+    engine.render(&mut out);
+    (out, engine.readings())
+}
+
+// Writes interleaved frames of 16 bit samples to a WAV file.
+pub fn write_wav(path: &Path, rate: u32, samples: Vec<i16>) -> io::Result<()> {
+    let header = Header::new(WAV_FORMAT_PCM, CHANNELS as u16, rate, 16);
+    let mut file = BufWriter::new(File::create(path)?);
+    wav::write(header, &BitDepth::Sixteen(samples), &mut file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::patch::Patch;
+    use crate::util::DEFAULT_RATE;
+
+    fn engine() -> Engine {
+        let patch = Patch::parse("wto1 = WaveTableOsc\nout wto1.out", DEFAULT_RATE).unwrap();
+        Engine::new(patch, 120)
+    }
+
+    #[test]
+    fn renders_bars_or_seconds() {
+        let bar_len = engine().bar_len();
+        let (samples, _) = render(engine(), Length::Bars(2));
+        assert_eq!(samples.len(), 2 * bar_len * CHANNELS);
+        let (samples, _) = render(engine(), Length::Seconds(0.5));
+        assert_eq!(samples.len(), DEFAULT_RATE as usize / 2 * CHANNELS);
+        assert!(samples.iter().any(|x| *x != 0));
+    }
+
+    #[test]
+    fn writes_a_wav_file() {
+        let (samples, _) = render(engine(), Length::Seconds(0.1));
+        let path = std::env::temp_dir().join(format!("jsynth-render-{}.wav", std::process::id()));
+        write_wav(&path, DEFAULT_RATE, samples.clone()).unwrap();
+        let (header, data) = wav::read(&mut File::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(header.channel_count, CHANNELS as u16);
+        assert_eq!(header.sampling_rate, DEFAULT_RATE);
+        assert_eq!(data, BitDepth::Sixteen(samples));
+    }
+}
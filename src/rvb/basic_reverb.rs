@@ -1,38 +1,58 @@
-use std::ops::{Index, IndexMut};
-
 use crate::util::Component;
-use crate::util::RATE;
+use crate::util::PortInfo;
+use crate::util::PortKind;
+
+const INPUTS: [PortInfo; 1] = [PortInfo {
+    name: "cv_in",
+    kind: PortKind::Audio,
+    unit: "",
+    min: i16::MIN,
+    max: i16::MAX,
+    default: 0,
+    description: "The dry signal.",
+}];
+
+const OUTPUTS: [PortInfo; 1] = [PortInfo {
+    name: "out",
+    kind: PortKind::Audio,
+    unit: "",
+    min: i16::MIN,
+    max: i16::MAX,
+    default: 0,
+    description: "The signal after three series all-pass delays.",
+}];
 
 pub struct BasicReverb {
-    buffer: [[i16; RATE as usize]; 3],
-    pub delay: [i16; 3],
+    // A second of samples for each delay.
+    buffer: Vec<Vec<i16>>,
+    // In samples, from 1 to a second.
+    pub delay: [usize; 3],
     pub gain: [f32; 3],
     cv_in: i16,
     out_cv: i16,
-    dummy: i16,
     counter: usize,
 }
 
 impl BasicReverb {
-    pub fn new() -> BasicReverb {
+    pub fn new(rate: u32) -> BasicReverb {
         BasicReverb {
-            buffer: [[0i16; RATE as usize]; 3],
-            delay: [0i16; 3],
+            buffer: vec![vec![0i16; rate as usize]; 3],
+            // A third, a fifth and an eighth of a second.
+            delay: [3, 5, 8].map(|x| (rate as usize / x).max(1)),
             gain: [0.25f32; 3],
             cv_in: 0i16,
             out_cv: 0i16,
-            dummy: 0i16,
             counter: 0usize,
         }
     }
 }
 
 impl Component for BasicReverb {
-    fn tick(&mut self) {}
+    fn tick(&mut self, _velocity: i16) {}
     fn step(&mut self) {
         let mut inv = self.cv_in;
         for i in 0..3 {
-            let delay = self.delay[i].abs() as usize;
+            let delay = self.delay[i];
             let gain = self.gain[i];
             let buffer = self.buffer.get_mut(i).unwrap();
             let counter = self.counter % delay;
@@ -49,31 +69,25 @@ impl Component for BasicReverb {
         self.counter += 1;
     }
 
-    fn inputs(&self) -> Vec<&'static str> {
-        vec!["cv_in"]
+    fn input_info(&self) -> &'static [PortInfo] {
+        &INPUTS
     }
 
-    fn outputs(&self) -> Vec<&'static str> {
-        vec!["out"]
+    fn output_info(&self) -> &'static [PortInfo] {
+        &OUTPUTS
     }
-}
-
-impl Index<&str> for BasicReverb {
-    type Output = i16;
 
-    fn index(&self, i: &str) -> &Self::Output {
-        match i {
-            "out" => &self.out_cv,
-            _ => &0,
+    fn input_mut(&mut self, slot: usize) -> &mut i16 {
+        match slot {
+            0 => &mut self.cv_in,
+            _ => panic!("BasicReverb has no input slot {}", slot),
         }
     }
-}
 
-impl IndexMut<&str> for BasicReverb {
-    fn index_mut(&mut self, i: &str) -> &mut Self::Output {
-        match i {
-            "cv_in" => &mut self.cv_in,
-            _ => &mut self.dummy,
+    fn output(&self, slot: usize) -> i16 {
+        match slot {
+            0 => self.out_cv,
+            _ => panic!("BasicReverb has no output slot {}", slot),
         }
     }
 }
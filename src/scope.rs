@@ -0,0 +1,149 @@
+use std::collections::VecDeque;
+
+use rtrb::Consumer;
+
+use crate::patch::PortRef;
+
+// The samples shown across the scope, from MIN_TIMEBASE to MAX_TIMEBASE in
+// powers of 2.
+pub const MIN_TIMEBASE: usize = 64;
+pub const MAX_TIMEBASE: usize = 16384;
+// The most points drawn, however long the timebase.
+const MAX_POINTS: usize = 512;
+
+// The UI's end of the scope: the samples of the port it's on, or of the
+// master output mixed down to mono, as the audio thread copies them out,
+// and where to start drawing them. It waits for the signal to rise through
+// `level` and starts there, so that a steady waveform stands still; if it
+// doesn't cross in time, it shows the latest samples instead.
+pub struct Scope {
+    samples: Consumer<i16>,
+    history: VecDeque<i16>,
+    pub rate: u32,
+    pub port: Option<PortRef>,
+    pub level: i16,
+    pub timebase: usize,
+}
+
+impl Scope {
+    pub fn new(samples: Consumer<i16>, rate: u32) -> Scope {
+        Scope {
+            samples,
+            history: VecDeque::with_capacity(2 * MAX_TIMEBASE),
+            rate,
+            port: None,
+            level: 0,
+            timebase: 1024,
+        }
+    }
+
+    // Takes what the audio thread has sent since the last time.
+    pub fn take(&mut self) {
+        while let Ok(s) = self.samples.pop() {
+            if self.history.len() == 2 * MAX_TIMEBASE {
+                self.history.pop_front();
+            }
+            self.history.push_back(s);
+        }
+    }
+
+    // Starts over, e.g. when the scope moves to another port.
+    pub fn clear(&mut self) {
+        self.take();
+        self.history.clear();
+    }
+
+    // The last `n` samples, or as many as there are.
+    pub fn latest(&self, n: usize) -> Vec<i16> {
+        let skip = self.history.len().saturating_sub(n);
+        self.history.iter().skip(skip).copied().collect()
+    }
+
+    pub fn zoom_in(&mut self) {
+        self.timebase = (self.timebase / 2).max(MIN_TIMEBASE);
+    }
+
+    pub fn zoom_out(&mut self) {
+        self.timebase = (self.timebase * 2).min(MAX_TIMEBASE);
+    }
+
+    // The first sample to draw, and whether the signal crossed `level`
+    // there: the latest crossing with a whole timebase after it.
+    fn start(&self) -> (usize, bool) {
+        let len = self.history.len();
+        let last = len.saturating_sub(self.timebase);
+        (1..=last)
+            .rev()
+            .find(|i| self.history[i - 1] < self.level && self.history[*i] >= self.level)
+            .map_or((last, false), |i| (i, true))
+    }
+
+    // The points to draw, with x in samples from the start, and whether
+    // they're triggered.
+    pub fn points(&self) -> (Vec<(f64, f64)>, bool) {
+        let (start, triggered) = self.start();
+        let step = (self.timebase / MAX_POINTS).max(1);
+        let points = self
+            .history
+            .iter()
+            .skip(start)
+            .take(self.timebase)
+            .enumerate()
+            .step_by(step)
+            .map(|(x, y)| (x as f64, *y as f64))
+            .collect();
+        (points, triggered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::engine::CHANNELS;
+    use crate::patch::Patch;
+    use crate::util::DEFAULT_RATE;
+
+    const LEN: usize = 8192;
+
+    #[test]
+    fn scope_follows_a_port_and_triggers() {
+        let patch = "wto1 = WaveTableOsc table=saw
+                     wto1.freq = 60
+                     vca1 = Vca
+                     vca1.amp_cv = 16384
+                     wto1.out -> vca1.in_cv
+                     out vca1.out";
+        let mut engine = Engine::new(Patch::parse(patch, DEFAULT_RATE).unwrap(), 480);
+        let mut scope = Scope::new(engine.scope(), DEFAULT_RATE);
+        engine.tap(Some(("wto1".to_string(), "out".to_string())));
+        let mut out = vec![0; LEN * CHANNELS];
+        engine.render(&mut out);
+        scope.take();
+
+        // A falling saw at 261.6 Hz, a cycle being about 168 samples, only
+        // rises through 0 where it jumps back up, so that's where it starts.
+        scope.timebase = 256;
+        let (points, triggered) = scope.points();
+        assert!(triggered);
+        assert_eq!(points.len(), 256);
+        assert!(points[0].1 > 16384.);
+        let wrap = (1..points.len()).find(|x| points[*x].1 > points[x - 1].1);
+        assert!(
+            matches!(wrap, Some(164..=172)),
+            "the saw wraps at {:?}",
+            wrap
+        );
+
+        // The Vca's output is at half scale, so it never crosses a level
+        // above that, and the scope runs free.
+        engine.tap(Some(("vca1".to_string(), "out".to_string())));
+        scope.clear();
+        engine.render(&mut out);
+        scope.take();
+        scope.level = 20000;
+        let (points, triggered) = scope.points();
+        assert!(!triggered);
+        assert!(points.iter().all(|(_, y)| y.abs() <= 16384.));
+    }
+}
@@ -1,31 +1,146 @@
-use std::ops::{Index, IndexMut};
+use std::sync::atomic::AtomicU16;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use std::sync::RwLock;
 
 use crate::util::Component;
+use crate::util::PortInfo;
+use crate::util::PortKind;
+
+// The transport's clock sets the tempo, unless built with `clock=wire`.
+const INPUTS: [PortInfo; 1] = [PortInfo {
+    name: "clock",
+    kind: PortKind::Trigger,
+    unit: "",
+    min: 0,
+    max: i16::MAX,
+    default: 0,
+    description: "With clock=wire, moves on a step each time it goes high, e.g. from a Clock.",
+}];
+
+const OUTPUTS: [PortInfo; 4] = [
+    PortInfo {
+        name: "gate",
+        kind: PortKind::Gate,
+        unit: "",
+        min: 0,
+        max: i16::MAX,
+        default: 0,
+        description: "High from the start of an active step until its beat length runs out.",
+    },
+    PortInfo {
+        name: "trigger",
+        kind: PortKind::Trigger,
+        unit: "",
+        min: 0,
+        max: i16::MAX,
+        default: 0,
+        description: "High for the first sample of an active step.",
+    },
+    PortInfo {
+        name: "beat",
+        kind: PortKind::Cv,
+        unit: "step",
+        min: 0,
+        max: 15,
+        default: 0,
+        description: "The step the sequencer is on.",
+    },
+    PortInfo {
+        name: "velocity",
+        kind: PortKind::Cv,
+        unit: "",
+        min: 0,
+        max: i16::MAX,
+        default: i16::MAX,
+        description: "How loud the groove makes the step the sequencer is on.",
+    },
+];
+
+// The steps of a sequencer that are on, a bit each with step 0 the lowest.
+// It's shared with the control thread, which changes it with a single
+// atomic store, so that the audio thread never waits to read it.
+#[derive(Debug, Default)]
+pub struct Pattern(AtomicU16);
+
+impl Pattern {
+    pub fn new(beats: [bool; 16]) -> Pattern {
+        let bits = (0..16)
+            .filter(|i| beats[*i])
+            .fold(0, |bits, i| bits | 1 << i);
+        Pattern(AtomicU16::new(bits))
+    }
+
+    pub fn get(&self, step: usize) -> bool {
+        self.0.load(Ordering::Relaxed) & (1 << step) != 0
+    }
+
+    pub fn beats(&self) -> [bool; 16] {
+        let bits = self.0.load(Ordering::Relaxed);
+        std::array::from_fn(|i| bits & (1 << i) != 0)
+    }
+
+    // Only one thread should set steps, or they could undo each other's.
+    pub fn set(&self, step: usize, on: bool) {
+        let bits = self.0.load(Ordering::Relaxed);
+        let bits = if on {
+            bits | 1 << step
+        } else {
+            bits & !(1 << step)
+        };
+        self.0.store(bits, Ordering::Relaxed);
+    }
+}
 
 pub struct BasicSeq {
-    tempo: i16,
-    beats: Arc<RwLock<[bool; 16]>>,
-    beat_len: Arc<RwLock<[i16; 16]>>,
+    pub pattern: Arc<Pattern>,
+    beat_len: [i16; 16],
     gate: i16,
     trigger: i16,
     counter: u32,
     beat: i16,
-    dummy: i16,
+    velocity: i16,
+    // Set by locate, so that the next tick plays `beat` rather than the
+    // step after it.
+    hold: bool,
+    // Whether the steps come from the clock input rather than the
+    // transport's ticks.
+    wired: bool,
+    clock: i16,
+    prev_clock: i16,
 }
 
 impl BasicSeq {
-    pub fn new(beats: Arc<RwLock<[bool; 16]>>, beat_len: Arc<RwLock<[i16; 16]>>) -> Self {
+    pub fn new(pattern: Arc<Pattern>, beat_len: [i16; 16], wired: bool) -> Self {
         BasicSeq {
-            tempo: 0,
-            beats: beats,
-            beat_len: beat_len,
+            pattern,
+            beat_len,
             gate: 0,
             trigger: 0,
             counter: 0,
             beat: 0,
-            dummy: 0,
+            velocity: i16::MAX,
+            hold: false,
+            wired,
+            clock: 0,
+            prev_clock: 0,
+        }
+    }
+
+    fn advance(&mut self, velocity: i16) {
+        self.velocity = velocity;
+        if self.hold {
+            self.hold = false;
+        } else {
+            self.beat = (self.beat + 1) % 16;
+        }
+        if self.pattern.get(self.beat as usize) {
+            self.gate = i16::max_value();
+            self.trigger = i16::max_value();
+            self.counter = 0;
+        } else {
+            self.gate = 0;
+            self.trigger = 0;
+            self.counter = 0;
         }
     }
 }
@@ -33,7 +148,11 @@ impl BasicSeq {
 impl Component for BasicSeq {
     fn step(&mut self) {
         self.trigger = 0;
-        if self.beats.read().unwrap()[self.beat as usize] {
+        if self.wired && self.clock > 0 && self.prev_clock <= 0 {
+            self.advance(i16::MAX);
+        }
+        self.prev_clock = self.clock;
+        if self.pattern.get(self.beat as usize) {
             let c = self.counter.wrapping_add(1);
             if c < self.counter {
                 self.counter = 0;
@@ -41,51 +160,47 @@ impl Component for BasicSeq {
             } else {
                 self.counter = c;
             }
-            if (1000 * (self.beat_len.read().unwrap()[self.beat as usize] as u32)) < self.counter {
+            if (1000 * (self.beat_len[self.beat as usize] as u32)) < self.counter {
                 self.gate = 0;
             }
         }
     }
-    fn tick(&mut self) {
-        self.beat = (self.beat + 1) % 16;
-        if self.beats.read().unwrap()[self.beat as usize] {
-            self.gate = i16::max_value();
-            self.trigger = i16::max_value();
-            self.counter = 0;
-        } else {
-            self.gate = 0;
-            self.trigger = 0;
-            self.counter = 0;
+    fn tick(&mut self, velocity: i16) {
+        if !self.wired {
+            self.advance(velocity);
         }
     }
 
-    fn inputs(&self) -> Vec<&'static str> {
-        vec!["tempo"]
+    fn locate(&mut self, step: u64) {
+        self.beat = (step % 16) as i16;
+        self.hold = true;
+        self.gate = 0;
+        self.trigger = 0;
+        self.counter = 0;
     }
 
-    fn outputs(&self) -> Vec<&'static str> {
-        vec!["gate", "trigger", "beat"]
+    fn input_info(&self) -> &'static [PortInfo] {
+        &INPUTS
     }
-}
 
-impl Index<&str> for BasicSeq {
-    type Output = i16;
+    fn output_info(&self) -> &'static [PortInfo] {
+        &OUTPUTS
+    }
 
-    fn index(&self, i: &str) -> &Self::Output {
-        match i {
-            "beat" => &self.beat,
-            "gate" => &self.gate,
-            "trigger" => &self.trigger,
-            _ => &0,
+    fn input_mut(&mut self, slot: usize) -> &mut i16 {
+        match slot {
+            0 => &mut self.clock,
+            _ => panic!("BasicSeq has no input slot {}", slot),
         }
     }
-}
 
-impl IndexMut<&str> for BasicSeq {
-    fn index_mut(&mut self, i: &str) -> &mut Self::Output {
-        match i {
-            "tempo" => &mut self.tempo,
-            _ => &mut self.dummy,
+    fn output(&self, slot: usize) -> i16 {
+        match slot {
+            0 => self.gate,
+            1 => self.trigger,
+            2 => self.beat,
+            3 => self.velocity,
+            _ => panic!("BasicSeq has no output slot {}", slot),
         }
     }
 }
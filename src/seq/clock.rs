@@ -0,0 +1,232 @@
+use crate::util;
+use crate::util::Component;
+use crate::util::PortInfo;
+use crate::util::PortKind;
+
+const INPUTS: [PortInfo; 3] = [
+    PortInfo {
+        name: "bpm",
+        kind: PortKind::Cv,
+        unit: "bpm",
+        min: 1,
+        max: 999,
+        default: util::TEMPO as i16,
+        description: "The tempo, in quarter notes a minute.",
+    },
+    PortInfo {
+        name: "run",
+        kind: PortKind::Gate,
+        unit: "",
+        min: 0,
+        max: i16::MAX,
+        default: i16::MAX,
+        description: "The clock runs while this is high, and holds where it is while it's low.",
+    },
+    PortInfo {
+        name: "reset",
+        kind: PortKind::Trigger,
+        unit: "",
+        min: 0,
+        max: i16::MAX,
+        default: 0,
+        description: "Starts the clock over from the top of a quarter note.",
+    },
+];
+
+// Pulses a quarter note for each of the gate outputs, in the same order.
+const DIVISIONS: [u64; 6] = [1, 2, 4, 8, 3, 6];
+
+const OUTPUTS: [PortInfo; 7] = [
+    PortInfo {
+        name: "quarter",
+        kind: PortKind::Gate,
+        unit: "",
+        min: 0,
+        max: i16::MAX,
+        default: 0,
+        description: "High for the first half of each quarter note.",
+    },
+    PortInfo {
+        name: "eighth",
+        kind: PortKind::Gate,
+        unit: "",
+        min: 0,
+        max: i16::MAX,
+        default: 0,
+        description: "High for the first half of each 8th note.",
+    },
+    PortInfo {
+        name: "sixteenth",
+        kind: PortKind::Gate,
+        unit: "",
+        min: 0,
+        max: i16::MAX,
+        default: 0,
+        description: "High for the first half of each 16th note.",
+    },
+    PortInfo {
+        name: "thirty_second",
+        kind: PortKind::Gate,
+        unit: "",
+        min: 0,
+        max: i16::MAX,
+        default: 0,
+        description: "High for the first half of each 32nd note.",
+    },
+    PortInfo {
+        name: "eighth_triplet",
+        kind: PortKind::Gate,
+        unit: "",
+        min: 0,
+        max: i16::MAX,
+        default: 0,
+        description: "High for the first half of each 8th note triplet, 3 to a quarter.",
+    },
+    PortInfo {
+        name: "sixteenth_triplet",
+        kind: PortKind::Gate,
+        unit: "",
+        min: 0,
+        max: i16::MAX,
+        default: 0,
+        description: "High for the first half of each 16th note triplet, 6 to a quarter.",
+    },
+    PortInfo {
+        name: "reset",
+        kind: PortKind::Trigger,
+        unit: "",
+        min: 0,
+        max: i16::MAX,
+        default: 0,
+        description: "High for the first sample after a reset.",
+    },
+];
+
+// A clock with its own tempo, for driving sequencers and arpeggiators
+// through wires at other rates than the transport's 16ths. Where it is in a
+// quarter note is kept as a count that goes up by the tempo each sample, a
+// quarter being 60 * rate, so that every division stays exact.
+pub struct Clock {
+    bpm: i16,
+    run: i16,
+    reset: i16,
+    prev_reset: i16,
+    quarter: u64,
+    phase: u64,
+    gates: [i16; DIVISIONS.len()],
+    reset_out: i16,
+}
+
+impl Clock {
+    pub fn new(rate: u32) -> Clock {
+        Clock {
+            bpm: util::TEMPO as i16,
+            run: i16::MAX,
+            reset: 0,
+            prev_reset: 0,
+            quarter: 60 * rate as u64,
+            phase: 0,
+            gates: [0; DIVISIONS.len()],
+            reset_out: 0,
+        }
+    }
+}
+
+impl Component for Clock {
+    fn step(&mut self) {
+        self.reset_out = 0;
+        if self.reset > 0 && self.prev_reset <= 0 {
+            self.phase = 0;
+            self.reset_out = i16::MAX;
+        }
+        self.prev_reset = self.reset;
+
+        if self.run <= 0 {
+            self.gates = [0; DIVISIONS.len()];
+            return;
+        }
+        for (gate, n) in self.gates.iter_mut().zip(DIVISIONS) {
+            *gate = if (self.phase * n) % self.quarter < self.quarter / 2 {
+                i16::MAX
+            } else {
+                0
+            };
+        }
+        self.phase = (self.phase + self.bpm.clamp(1, 999) as u64) % self.quarter;
+    }
+
+    fn tick(&mut self, _velocity: i16) {}
+
+    // Lines the clock up with the transport's 16ths.
+    fn locate(&mut self, step: u64) {
+        self.phase = (step % 4) * self.quarter / 4;
+    }
+
+    fn input_info(&self) -> &'static [PortInfo] {
+        &INPUTS
+    }
+
+    fn output_info(&self) -> &'static [PortInfo] {
+        &OUTPUTS
+    }
+
+    fn input_mut(&mut self, slot: usize) -> &mut i16 {
+        match slot {
+            0 => &mut self.bpm,
+            1 => &mut self.run,
+            2 => &mut self.reset,
+            _ => panic!("Clock has no input slot {}", slot),
+        }
+    }
+
+    fn output(&self, slot: usize) -> i16 {
+        match slot {
+            0..=5 => self.gates[slot],
+            6 => self.reset_out,
+            _ => panic!("Clock has no output slot {}", slot),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::engine::Engine;
+    use crate::engine::CHANNELS;
+    use crate::patch::Patch;
+    use crate::util::DEFAULT_RATE;
+
+    const LEN: usize = 8192;
+
+    #[test]
+    fn clock_divides_the_quarter() {
+        // Where each of the ports goes high, in LEN samples: a quarter is
+        // 5512.5 samples at 480 bpm, so about one and a half quarters.
+        let edges = |port: &str| {
+            let patch = format!(
+                "clk1 = Clock
+                 clk1.bpm = 480
+                 seq1 = BasicSeq beats=0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15 clock=wire
+                 clk1.sixteenth -> seq1.clock
+                 out {}",
+                port
+            );
+            let mut engine = Engine::new(Patch::parse(&patch, DEFAULT_RATE).unwrap(), 480);
+            let mut out = vec![0; LEN * CHANNELS];
+            engine.render(&mut out);
+            let out = out.iter().step_by(CHANNELS).collect::<Vec<&i16>>();
+            (0..out.len())
+                .filter(|n| *out[*n] != 0 && (*n == 0 || *out[n - 1] == 0))
+                .collect::<Vec<usize>>()
+        };
+
+        assert_eq!(edges("clk1.quarter"), vec![0, 5513]);
+        assert_eq!(edges("clk1.eighth").len(), 3);
+        assert_eq!(edges("clk1.thirty_second").len(), 12);
+        assert_eq!(edges("clk1.eighth_triplet").len(), 5);
+        assert_eq!(edges("clk1.sixteenth_triplet").len(), 9);
+        let sixteenths = edges("clk1.sixteenth");
+        assert_eq!(sixteenths.len(), 6);
+        // The sequencer follows the wire rather than the transport's ticks.
+        assert_eq!(edges("seq1.trigger"), sixteenths);
+    }
+}
@@ -1,3 +1,6 @@
 mod basic;
+mod clock;
 
 pub use basic::BasicSeq;
+pub use basic::Pattern;
+pub use clock::Clock;
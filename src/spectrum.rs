@@ -0,0 +1,248 @@
+use std::f64::consts::PI;
+use std::fmt;
+
+// Samples in each analysis; a bin is rate / FFT_LEN wide, about 10.8 Hz
+// at 44.1 kHz.
+pub const FFT_LEN: usize = 4096;
+// The quietest level shown: about what 16 bits can hold.
+pub const FLOOR_DB: f64 = -96.;
+// The lowest frequency shown.
+const LOW_HZ: f64 = 20.;
+// The most points drawn; bins are merged, keeping the loudest, above that.
+const MAX_POINTS: usize = 256;
+// How fast held peaks fall, a frame at a time.
+const PEAK_FALL_DB: f64 = 0.5;
+
+// What each analysis is shaped by before the FFT. Rectangular keeps
+// the bins narrowest, but spreads anything between bins far and wide;
+// Hann and Blackman trade width for less spreading.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Window {
+    Rectangular,
+    Hann,
+    Blackman,
+}
+
+impl Window {
+    pub fn next(self) -> Window {
+        match self {
+            Window::Rectangular => Window::Hann,
+            Window::Hann => Window::Blackman,
+            Window::Blackman => Window::Rectangular,
+        }
+    }
+
+    fn at(self, n: usize) -> f64 {
+        let x = 2. * PI * n as f64 / (FFT_LEN - 1) as f64;
+        match self {
+            Window::Rectangular => 1.,
+            Window::Hann => 0.5 - 0.5 * x.cos(),
+            Window::Blackman => 0.42 - 0.5 * x.cos() + 0.08 * (2. * x).cos(),
+        }
+    }
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Window::Rectangular => "rectangular",
+            Window::Hann => "Hann",
+            Window::Blackman => "Blackman",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+// The level of each frequency in the latest samples, in dB from full
+// scale, with the peaks held and falling slowly.
+pub struct Spectrum {
+    rate: u32,
+    pub window: Window,
+    db: Vec<f64>,
+    peaks: Vec<f64>,
+}
+
+impl Spectrum {
+    pub fn new(rate: u32) -> Spectrum {
+        Spectrum {
+            rate,
+            window: Window::Hann,
+            db: vec![FLOOR_DB; FFT_LEN / 2],
+            peaks: vec![FLOOR_DB; FFT_LEN / 2],
+        }
+    }
+
+    // Analyses the last FFT_LEN of `samples`, as if there were silence
+    // before them if there are fewer.
+    pub fn analyse(&mut self, samples: &[i16]) {
+        let samples = &samples[samples.len().saturating_sub(FFT_LEN)..];
+        let pad = FFT_LEN - samples.len();
+        let mut re = vec![0.; FFT_LEN];
+        let mut im = vec![0.; FFT_LEN];
+        let mut gain = 0.;
+        for (n, x) in re.iter_mut().enumerate() {
+            let w = self.window.at(n);
+            gain += w;
+            if n >= pad {
+                *x = w * samples[n - pad] as f64 / -(i16::MIN as f64);
+            }
+        }
+        fft(&mut re, &mut im);
+
+        // So that a full scale sine on a bin is 0 dB, whatever the window.
+        for (k, db) in self.db.iter_mut().enumerate() {
+            let mag = 2. * re[k].hypot(im[k]) / gain;
+            *db = (20. * mag.log10()).max(FLOOR_DB);
+        }
+        for (peak, db) in self.peaks.iter_mut().zip(self.db.iter()) {
+            *peak = (*peak - PEAK_FALL_DB).max(*db);
+        }
+    }
+
+    pub fn clear_peaks(&mut self) {
+        self.peaks.iter_mut().for_each(|x| *x = FLOOR_DB);
+    }
+
+    // The frequency of the loudest bin, and its level.
+    pub fn loudest(&self) -> (f64, f64) {
+        let (k, db) = self
+            .db
+            .iter()
+            .enumerate()
+            .skip(1)
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .unwrap();
+        (self.hz(k), *db)
+    }
+
+    // The log10 of the lowest and highest frequencies shown, for the axis.
+    pub fn bounds(&self) -> [f64; 2] {
+        [LOW_HZ.log10(), (self.rate as f64 / 2.).log10()]
+    }
+
+    // The points to draw, with x the log10 of the frequency and y in dB.
+    pub fn levels(&self) -> Vec<(f64, f64)> {
+        self.merge(&self.db)
+    }
+
+    pub fn peaks(&self) -> Vec<(f64, f64)> {
+        self.merge(&self.peaks)
+    }
+
+    fn hz(&self, k: usize) -> f64 {
+        k as f64 * self.rate as f64 / FFT_LEN as f64
+    }
+
+    // Merges the bins into at most MAX_POINTS bands, evenly spaced on the
+    // log axis, keeping the loudest bin of each.
+    fn merge(&self, db: &[f64]) -> Vec<(f64, f64)> {
+        let [lo, hi] = self.bounds();
+        let mut points: Vec<(f64, f64)> = vec![];
+        let mut last_band = usize::MAX;
+        for (k, db) in db.iter().enumerate().skip(1) {
+            let x = self.hz(k).log10();
+            if x < lo {
+                continue;
+            }
+            let band = ((x - lo) / (hi - lo) * MAX_POINTS as f64) as usize;
+            match points.last_mut() {
+                Some(p) if band == last_band => p.1 = p.1.max(*db),
+                _ => points.push((x, *db)),
+            }
+            last_band = band;
+        }
+        points
+    }
+}
+
+// An in-place, radix-2 FFT; the length must be a power of 2.
+fn fft(re: &mut [f64], im: &mut [f64]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let (sin, cos) = (-2. * PI / len as f64).sin_cos();
+        for start in (0..n).step_by(len) {
+            let (mut wr, mut wi) = (1., 0.);
+            for k in 0..len / 2 {
+                let (a, b) = (start + k, start + k + len / 2);
+                let tr = re[b] * wr - im[b] * wi;
+                let ti = re[b] * wi + im[b] * wr;
+                re[b] = re[a] - tr;
+                im[b] = im[a] - ti;
+                re[a] += tr;
+                im[a] += ti;
+                let w = wr * cos - wi * sin;
+                wi = wr * sin + wi * cos;
+                wr = w;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::DEFAULT_RATE;
+
+    const LEN: usize = 8192;
+
+    #[test]
+    fn spectrum_finds_the_note_and_windows_the_leakage() {
+        // 440 Hz, which falls between two bins, at half scale.
+        let out = (0..LEN)
+            .map(|n| {
+                let t = n as f64 / DEFAULT_RATE as f64;
+                (16384. * (2. * PI * 440. * t).sin()) as i16
+            })
+            .collect::<Vec<i16>>();
+        // The level well away from the note, at around 4 kHz.
+        let far = |spectrum: &Spectrum| {
+            let levels = spectrum.levels();
+            levels.iter().find(|(x, _)| *x > 4000f64.log10()).unwrap().1
+        };
+
+        let mut spectrum = Spectrum::new(DEFAULT_RATE);
+        spectrum.window = Window::Rectangular;
+        spectrum.analyse(&out);
+        let (hz, db) = spectrum.loudest();
+        assert!((430. ..450.).contains(&hz), "loudest at {} Hz", hz);
+        assert!((-10. ..-6.).contains(&db), "loudest at {} dB", db);
+        let rectangular = far(&spectrum);
+
+        spectrum.window = Window::Blackman;
+        spectrum.analyse(&out);
+        assert!((430. ..450.).contains(&spectrum.loudest().0));
+        assert!(
+            far(&spectrum) < rectangular - 10.,
+            "{} dB with Blackman, {} dB without",
+            far(&spectrum),
+            rectangular
+        );
+
+        // The peaks hold after the note stops, and fall slowly.
+        let peak = |spectrum: &Spectrum| {
+            let peaks = spectrum.peaks();
+            peaks.iter().map(|x| x.1).fold(FLOOR_DB, f64::max)
+        };
+        spectrum.analyse(&[0; FFT_LEN]);
+        assert_eq!(spectrum.loudest().1, FLOOR_DB);
+        assert!(peak(&spectrum) > -7.);
+        spectrum.clear_peaks();
+        assert_eq!(peak(&spectrum), FLOOR_DB);
+    }
+}
@@ -0,0 +1,267 @@
+use std::fmt;
+
+use crate::groove::Groove;
+
+// How late every other 16th comes, as a percentage of a pair of 16ths:
+// 50 is straight and 66.7 a triplet shuffle.
+pub const MIN_SWING: f64 = 50.;
+pub const MAX_SWING: f64 = 75.;
+
+// Whether the 16th note clock is running. Paused and stopped both stop
+// it; stopping also goes back to the first step. Components keep being
+// stepped either way, so that notes can ring out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State {
+    Playing,
+    Paused,
+    Stopped,
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            State::Playing => "playing",
+            State::Paused => "paused",
+            State::Stopped => "stopped",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+// A tick of the clock: the offset of its sample into a block, and the
+// velocity the groove gives its step.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tick {
+    pub at: usize,
+    pub velocity: i16,
+}
+
+// The 16th note clock that ticks the components, and where it is. The
+// ticks are moved off the grid by the swing and the groove: the time from
+// one tick to the next is a 16th, plus how late the next step is, less how
+// late this one was.
+pub struct Transport {
+    state: State,
+    bpm: f64,
+    rate: u32,
+    swing: f64,
+    groove: Groove,
+    cycles_per_16th: u64,
+    cycle_counter: u64,
+    // Samples from the last tick to the next.
+    due: u64,
+    // Whether the next tick comes on the next sample, after locate.
+    located: bool,
+    // The step the next tick plays, counted from the start.
+    next: u64,
+}
+
+impl Transport {
+    // It starts playing, with the first tick a 16th in, on step 1.
+    pub fn new(bpm: f64, rate: u32) -> Transport {
+        let mut transport = Transport {
+            state: State::Playing,
+            bpm,
+            rate,
+            swing: MIN_SWING,
+            groove: Groove::straight(),
+            cycles_per_16th: 0,
+            cycle_counter: 0,
+            due: 0,
+            located: false,
+            next: 1,
+        };
+        transport.set_bpm(bpm);
+        transport
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    pub fn bpm(&self) -> f64 {
+        self.bpm
+    }
+
+    pub fn swing(&self) -> f64 {
+        self.swing
+    }
+
+    // Samples in a 16th, at the current tempo.
+    pub fn cycles_per_16th(&self) -> u64 {
+        self.cycles_per_16th
+    }
+
+    // At least 1 bpm, so that the clock keeps ticking.
+    pub fn set_bpm(&mut self, bpm: f64) {
+        self.bpm = bpm.max(1.);
+        self.cycles_per_16th = ((60. / (4. * self.bpm)) * (self.rate as f64)) as u64;
+        self.cycles_per_16th = self.cycles_per_16th.max(1);
+        self.retime();
+    }
+
+    // Within MIN_SWING and MAX_SWING.
+    pub fn set_swing(&mut self, swing: f64) {
+        self.swing = swing.clamp(MIN_SWING, MAX_SWING);
+        self.retime();
+    }
+
+    // Returns the groove it replaces, so that it can be dropped somewhere
+    // other than the audio thread.
+    pub fn set_groove(&mut self, groove: Groove) -> Groove {
+        let old = std::mem::replace(&mut self.groove, groove);
+        self.retime();
+        old
+    }
+
+    pub fn play(&mut self) {
+        self.state = State::Playing;
+    }
+
+    pub fn pause(&mut self) {
+        if self.state == State::Playing {
+            self.state = State::Paused;
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.state = State::Stopped;
+        self.locate(0);
+    }
+
+    // Makes `step` the next to play, on the first sample once playing.
+    // Components are told separately, with Component::locate.
+    pub fn locate(&mut self, step: u64) {
+        self.next = step;
+        self.cycle_counter = 0;
+        self.due = 1;
+        self.located = true;
+    }
+
+    // Fills `ticks` with the ticks in the next `len` samples.
+    pub fn ticks(&mut self, len: usize, ticks: &mut Vec<Tick>) {
+        ticks.clear();
+        if self.state != State::Playing {
+            return;
+        }
+        for n in 0..len {
+            self.cycle_counter += 1;
+            if self.cycle_counter >= self.due {
+                ticks.push(Tick {
+                    at: n,
+                    velocity: self.groove.velocity(self.next),
+                });
+                self.next += 1;
+                self.cycle_counter = 0;
+                self.located = false;
+                self.retime();
+            }
+        }
+    }
+
+    // How late `step` comes, in samples.
+    fn offset(&self, step: u64) -> i64 {
+        let mut late = self.groove.timing(step);
+        if step % 2 == 1 {
+            late += (self.swing - MIN_SWING) / MIN_SWING;
+        }
+        (late * self.cycles_per_16th as f64) as i64
+    }
+
+    // Works out when the next tick is due, after a change to the tempo, the
+    // swing or the groove, keeping it from being overdue.
+    fn retime(&mut self) {
+        if self.located {
+            return;
+        }
+        let c = self.cycles_per_16th as i64;
+        let due = c + self.offset(self.next) - self.offset(self.next.saturating_sub(1));
+        self.due = due.max(1) as u64;
+        self.cycle_counter = self.cycle_counter.min(self.due - 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::engine::CHANNELS;
+    use crate::patch::Patch;
+    use crate::util::DEFAULT_RATE;
+
+    // Fast enough that a render holds a few 16ths: one is 1378 samples.
+    const TEMPO: u64 = 480;
+    const LEN: usize = 8192;
+
+    #[test]
+    fn transport_stops_locates_and_changes_tempo() {
+        let patch = Patch::parse(
+            "seq1 = BasicSeq beats=0,5
+             out seq1.trigger",
+            DEFAULT_RATE,
+        )
+        .unwrap();
+        let mut engine = Engine::new(patch, TEMPO);
+        // Where the triggers are in the next `len` samples.
+        let triggers = |engine: &mut Engine, len: usize| {
+            let mut out = vec![0; len * CHANNELS];
+            engine.render(&mut out);
+            out.iter()
+                .step_by(CHANNELS)
+                .enumerate()
+                .filter(|(_, x)| **x != 0)
+                .map(|(n, _)| n)
+                .collect::<Vec<usize>>()
+        };
+
+        // Step 5, as step 0 only comes round after 16 steps.
+        assert_eq!(triggers(&mut engine, LEN), vec![5 * 1378 - 1]);
+
+        engine.stop();
+        assert_eq!(triggers(&mut engine, LEN), vec![]);
+        engine.transport_mut().play();
+        assert_eq!(triggers(&mut engine, 100), vec![0]);
+
+        engine.transport_mut().pause();
+        assert_eq!(triggers(&mut engine, LEN), vec![]);
+        engine.locate(5);
+        engine.transport_mut().play();
+        assert_eq!(triggers(&mut engine, 100), vec![0]);
+
+        engine.transport_mut().set_bpm(2. * TEMPO as f64);
+        engine.locate(4);
+        assert_eq!(triggers(&mut engine, 1000), vec![689]);
+    }
+
+    #[test]
+    fn swing_and_groove_move_the_ticks() {
+        let patch = "seq1 = BasicSeq beats=0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15
+                     out seq1.trigger seq1.velocity";
+        let mut engine = Engine::new(Patch::parse(patch, DEFAULT_RATE).unwrap(), TEMPO);
+        // A 16th is 1378 samples; at 75% swing the odd steps are half of that
+        // late, and the groove moves step 2 a further 10% late and step 3 20%
+        // early, and makes step 2 quieter.
+        engine.transport_mut().set_swing(75.);
+        engine
+            .transport_mut()
+            .set_groove(Groove::parse("0 0\n0 0\n10 -50\n-20 0").unwrap());
+        let mut out = vec![0; LEN * CHANNELS];
+        engine.render(&mut out);
+        let triggers = out
+            .chunks(CHANNELS)
+            .enumerate()
+            .filter(|(_, x)| x[0] != 0)
+            .map(|(n, x)| (n, x[1]))
+            .collect::<Vec<(usize, i16)>>();
+        assert_eq!(
+            triggers,
+            vec![
+                (1378 + 689 - 1, i16::MAX),
+                (2 * 1378 + 137 - 1, 16384),
+                (3 * 1378 + 413 - 1, i16::MAX),
+                (4 * 1378 - 1, i16::MAX),
+                (5 * 1378 + 689 - 1, i16::MAX),
+            ]
+        );
+    }
+}
@@ -3,16 +3,14 @@
 //use std::path::Path;
 //use wav;
 
+use std::collections::HashMap;
 use std::sync::mpsc::TryRecvError;
-use std::time;
 
 use std::sync::atomic::AtomicI16;
-use std::sync::atomic::AtomicU64;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
 use crate::tui_util::StatefulList;
-use argh::FromArgs;
 use std::{error::Error, io, time::Duration};
 use termion::{event::Key, input::MouseTerminal, raw::IntoRawMode, screen::AlternateScreen};
 use tui::{backend::TermionBackend, Terminal};
@@ -21,7 +19,10 @@ use tui::{
     style::{Color, Modifier, Style},
     symbols,
     text::{Span, Spans, Text},
-    widgets::{Block, Borders, Gauge, List, ListItem, Paragraph, Sparkline},
+    widgets::{
+        Axis, Block, Borders, Chart, Dataset, Gauge, GraphType, List, ListItem, Paragraph,
+        Sparkline,
+    },
 };
 
 use std::path::PathBuf;
@@ -29,6 +30,18 @@ use std::sync::mpsc::Receiver;
 use std::sync::mpsc::Sender;
 
 use crate::arp::TtetNote;
+use crate::groove::Groove;
+use crate::osc::WaveTableChoice;
+use crate::out::Load;
+use crate::out::Readings;
+use crate::patch;
+use crate::patch::PatchError;
+use crate::patch::PortRef;
+use crate::patch::Statement;
+use crate::scope::Scope;
+use crate::spectrum;
+use crate::spectrum::Spectrum;
+use crate::transport;
 
 #[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq)]
 pub struct SingleCycleWaveFormItem {
@@ -49,47 +62,124 @@ pub enum Cmd {
     Obeat(i16, bool),
     FileWaveTable(SingleCycleWaveFormItem),
     Scale(TtetNote), // Major Scale only right now, and only octave 4
-    AdsrAttackFor(i16),
-    AdsrAttackTo(i16),
-    AdsrDecayFor(i16),
-    AdsrSustainAt(i16),
-    AdsrReleaseFor(i16),
+    // Notes from the keyboard, for every Voices component.
+    NoteOn(i16),
+    NoteOff(i16),
+    // Sets an input of a component, clamped to the port's range.
+    SetParam {
+        component: String,
+        port: String,
+        value: i16,
+    },
+    // Sent back once an input has been set, with the value it was set to.
+    ParamChanged {
+        component: String,
+        port: String,
+        value: i16,
+    },
+    // Wires that feed back into the patch, and so arrive a sample late.
+    // Also sent after each change to the patch.
+    DelayedWires(Vec<(PortRef, PortRef)>),
+    // Changes to the patch, made between blocks while it plays.
+    AddComponent {
+        name: String,
+        kind: String,
+        args: Vec<String>,
+    },
+    RemoveComponent(String),
+    Connect(PortRef, PortRef),
+    Disconnect(PortRef, PortRef),
+    // Sent back when a change to the patch can't be made.
+    RepatchFailed(String),
+    // The output device, and any errors from it while it plays.
+    Device(String),
+    DeviceError(String),
+    // The share of the real-time budget each component has taken lately.
+    Cpu(Vec<(String, f32)>),
+    // The levels of the master and the mixers: sent to the audio thread to
+    // be filled in, and back to the UI. ClearClips starts the clip counts
+    // over.
+    Meters(Readings),
+    ClearClips,
+    // A wavetable for wto1, once it's been loaded.
+    WaveTable(WaveTableChoice),
+    // The transport. Stopping also goes back to step 0, and Locate makes
+    // the given step the next to play.
+    Play,
+    Pause,
+    Stop,
+    Locate(u64),
+    Tempo(f64),
+    Swing(f64),
+    // A groove file to load, or None to play straight, and the groove once
+    // it's been loaded.
+    LoadGroove(Option<PathBuf>),
+    Groove(Groove),
+    // The output the scope and spectrum show, or None for the master.
+    Scope(Option<PortRef>),
+    // Sent back after each of those, and once at the start.
+    Transport {
+        state: transport::State,
+        bpm: f64,
+        swing: f64,
+    },
 }
 
+// The sliders in the ADSR panel: the component and port each one sets, its
+// label, and the keys that turn it up and down.
+const ADSR_SLIDERS: [(&str, &str, &str, char, char); 5] = [
+    ("adsr1", "attack_for", "Attack For", 'd', 'c'),
+    ("adsr1", "attack_to", "Attack To", 'f', 'v'),
+    ("adsr1", "decay_for", "Decay For", 'g', 'b'),
+    ("adsr1", "sustain_at", "Sustain At", 'h', 'n'),
+    ("adsr1", "release_for", "Release For", 'j', 'm'),
+];
+
+// The most meters shown, so that a patch with many mixers leaves room for
+// the rest.
+const MAX_METERS: usize = 8;
+
+// The keys that play notes, a semitone apart, laid out like a piano: the
+// bottom two rows of letters, then the top two.
+const KEYS: [char; 25] = [
+    'z', 's', 'x', 'd', 'c', 'v', 'g', 'b', 'h', 'n', 'j', 'm', 'q', '2', 'w', '3', 'e', 'r', '5',
+    't', '6', 'y', '7', 'u', 'i',
+];
+
 pub fn ui_loop(
     tx: Sender<Cmd>,
     rx2: Receiver<Cmd>,
     beat: Arc<AtomicI16>,
-    measured_xtime: Arc<AtomicU64>,
-    target_inc: u128,
+    load: Arc<Load>,
+    mut scope: Scope,
     single_cycle_wave_forms: &Vec<SingleCycleWaveFormItem>,
+    tick_rate: u64,
 ) -> Result<(), Box<dyn Error>> {
     let mut arp1_scale = TtetNote::A;
     let mut scwf_state = StatefulList::with_items(single_cycle_wave_forms);
 
     let mut last_scwf_i = usize::max_value();
 
-    let mut adsr_attack_for = 0i16;
-    let mut adsr_attack_to = 0i16;
-    let mut adsr_decay_for = 0i16;
-    let mut adsr_sustain_at = 0i16;
-    let mut adsr_release_for = 0i16;
-
-    #[derive(Debug, FromArgs)]
-    #[argh(description = "options")]
-    struct Cli {
-        /// time in ms between two ticks.
-        #[argh(option, default = "65", description = "tick rate in ms")]
-        tick_rate: u64,
-        /// whether unicode symbols are used to improve the overall look of the app
-        #[argh(option, default = "true", description = "unicode?")]
-        enhanced_graphics: bool,
-    }
-
-    let cli: Cli = argh::from_env();
+    let mut params: HashMap<PortRef, i16> = HashMap::new();
+    let mut delayed_wires = String::new();
+    let mut device = String::new();
+    let mut cpu = String::new();
+    let mut meters = Readings::default();
+    let mut state = transport::State::Stopped;
+    let mut bpm = 0.;
+    let mut swing = 0.;
+    let mut spectrum = Spectrum::new(scope.rate);
+    // The line being typed at the repatching prompt, if it's open, and the
+    // result of the last line entered.
+    let mut prompt: Option<String> = None;
+    let mut repatch_result = String::new();
+    // The note of the lowest key, while the keyboard is playing notes, and
+    // the notes being held.
+    let mut keys: Option<i16> = None;
+    let mut held: Vec<i16> = vec![];
 
     let events = Events::with_config(Config {
-        tick_rate: Duration::from_millis(cli.tick_rate),
+        tick_rate: Duration::from_millis(tick_rate),
         ..Config::default()
     });
     let mut beats = [0u64; 16];
@@ -100,38 +190,43 @@ pub fn ui_loop(
     let backend = TermionBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
     let mut counter = 0;
-    let mut xtimes = [0u64; 100];
-    let mut beat_start = time::Instant::now();
-    let mut comp_tempo = 0.;
-    let mut prev_beat = 15;
+    let mut loads = [0u64; 100];
     loop {
         let beat = (beat.load(Ordering::Relaxed) as usize) % 16;
 
-        if beat == 0 && prev_beat != 0 {
-            beat_start = time::Instant::now();
-        } else if beat == 4 && prev_beat != 4 {
-            comp_tempo = 60. * 1_000_000_000. / (beat_start.elapsed().as_nanos() as f64);
-        }
-
-        let measured_xtime = measured_xtime.load(Ordering::Relaxed);
-        xtimes[counter % 100] = measured_xtime;
+        loads[counter % 100] = (100. * load.load()) as u64;
+        scope.take();
+        let (points, triggered) = scope.points();
+        spectrum.analyse(&scope.latest(spectrum::FFT_LEN));
+        let (levels, peaks) = (spectrum.levels(), spectrum.peaks());
         terminal
             .draw(|f| {
-                let xtime_avg = xtimes.iter().sum::<u64>() as f64 / xtimes.len() as f64;
                 let text = vec![
                     Spans::from(format!("arp1.scale={} a=Up z=Down", arp1_scale)),
-                    Spans::from(""),
+                    Spans::from(match (&prompt, keys) {
+                        (Some(line), _) => format!("> {}_", line),
+                        (None, Some(base)) => format!(
+                            "keys from note {} (Left/Right octave, Tab to stop): {:?}",
+                            base, held
+                        ),
+                        (None, None) if repatch_result.is_empty() => {
+                            ": to change the patch, Tab to play notes".to_string()
+                        }
+                        (None, None) => repatch_result.clone(),
+                    }),
                     Spans::from(format!(
-                        "{:02} {:5.4} ::::: {:10.4} {:10.4} {:10.4} ::::: {:10.4} {:10.4} {:10.4}",
+                        "{:02} {} {:.1} bpm swing {:.0}% ::::: load {:5.1}% peak {:5.1}% overruns {}",
                         beat,
-                        comp_tempo,
-                        0,
-                        0,
-                        0,
-                        measured_xtime,
-                        xtime_avg,
-                        ((256 * target_inc) as f64) - xtime_avg,
+                        state,
+                        bpm,
+                        swing,
+                        100. * load.load(),
+                        100. * load.peak(),
+                        load.overruns(),
                     )),
+                    Spans::from(format!("cpu: {}", cpu)),
+                    Spans::from(format!("1-sample feedback: {}", delayed_wires)),
+                    Spans::from(format!("output: {}", device)),
                 ];
                 let block = Block::default().borders(Borders::ALL).title(Span::styled(
                     "Pitch",
@@ -140,15 +235,17 @@ pub fn ui_loop(
                         .add_modifier(Modifier::BOLD),
                 ));
                 let paragraph = Paragraph::new(text).block(block);
+                let meters = meters.named();
                 let chunks = Layout::default()
                     .constraints(
                         [
-                            Constraint::Length(6),
+                            Constraint::Length(8),
                             Constraint::Length(6),
                             Constraint::Min(0),
                             Constraint::Length(2),
                             Constraint::Length(2),
                             Constraint::Length(2),
+                            Constraint::Length(meters.len().min(MAX_METERS) as u16 + 2),
                             Constraint::Length(15),
                         ]
                         .as_ref(),
@@ -193,17 +290,49 @@ pub fn ui_loop(
                 f.render_widget(sparkline, chunks[4]);
 
                 let sparkline = Sparkline::default()
-                    .block(Block::default().title("loop xtime:"))
+                    .block(Block::default().title("load:"))
                     .style(Style::default().fg(Color::Green))
-                    .data(&xtimes)
+                    .data(&loads)
+                    .max(100)
                     .bar_set(symbols::bar::NINE_LEVELS);
                 f.render_widget(sparkline, chunks[5]);
 
+                // Peaks from -60 dB up, red once anything has clipped.
+                f.render_widget(
+                    Block::default()
+                        .title("Meters (k clears clips)")
+                        .borders(Borders::ALL),
+                    chunks[6],
+                );
+                let mchunks = Layout::default()
+                    .constraints(vec![Constraint::Length(1); meters.len().min(MAX_METERS)])
+                    .margin(1)
+                    .split(chunks[6]);
+                for ((name, reading), area) in meters.iter().zip(mchunks) {
+                    let color = if reading.clips > 0 {
+                        Color::Red
+                    } else {
+                        Color::Green
+                    };
+                    let gauge = Gauge::default()
+                        .ratio(((reading.peak_db() as f64 + 60.) / 60.).clamp(0., 1.))
+                        .label(format!("{:12} {}", name, reading))
+                        .gauge_style(Style::default().fg(color));
+                    f.render_widget(gauge, area);
+                }
+
                 let vchunks = Layout::default()
                     .direction(Direction::Horizontal)
-                    .constraints([Constraint::Length(30), Constraint::Length(30)].as_ref())
+                    .constraints(
+                        [
+                            Constraint::Length(30),
+                            Constraint::Length(30),
+                            Constraint::Min(0),
+                        ]
+                        .as_ref(),
+                    )
                     .margin(1)
-                    .split(chunks[6]);
+                    .split(chunks[7]);
 
                 let sswf_list = List::new(
                     single_cycle_wave_forms
@@ -217,6 +346,84 @@ pub fn ui_loop(
                 .highlight_symbol(">>");
                 f.render_stateful_widget(sswf_list, vchunks[0], &mut scwf_state.state);
 
+                let schunks = Layout::default()
+                    .direction(Direction::Horizontal)
+                    .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+                    .split(vchunks[2]);
+
+                let title = match &scope.port {
+                    Some((component, port)) => format!(
+                        "Scope {}.{} ({} samples, {} at {}) </> [/]",
+                        component,
+                        port,
+                        scope.timebase,
+                        if triggered { "triggered" } else { "free" },
+                        scope.level
+                    ),
+                    None => format!(
+                        "Scope master ({} samples, {} at {}) </> [/]",
+                        scope.timebase,
+                        if triggered { "triggered" } else { "free" },
+                        scope.level
+                    ),
+                };
+                let dataset = Dataset::default()
+                    .marker(symbols::Marker::Braille)
+                    .graph_type(GraphType::Line)
+                    .style(Style::default().fg(Color::Green))
+                    .data(&points);
+                let chart = Chart::new(vec![dataset])
+                    .block(Block::default().title(title).borders(Borders::ALL))
+                    .x_axis(Axis::default().bounds([0., scope.timebase as f64]))
+                    .y_axis(Axis::default().bounds([i16::MIN as f64, i16::MAX as f64]));
+                f.render_widget(chart, schunks[0]);
+
+                let (hz, db) = spectrum.loudest();
+                let title = format!(
+                    "Spectrum {}: {:.0} Hz at {:.1} dB, {} window (o), peaks (p)",
+                    match &scope.port {
+                        Some((component, port)) => format!("{}.{}", component, port),
+                        None => "master".to_string(),
+                    },
+                    hz,
+                    db,
+                    spectrum.window
+                );
+                let [lo, hi] = spectrum.bounds();
+                let hz_label = |x: f64| {
+                    let hz = 10f64.powf(x);
+                    Span::from(if hz < 1000. {
+                        format!("{:.0}", hz)
+                    } else {
+                        format!("{:.1}k", hz / 1000.)
+                    })
+                };
+                let datasets = vec![
+                    Dataset::default()
+                        .marker(symbols::Marker::Dot)
+                        .graph_type(GraphType::Scatter)
+                        .style(Style::default().fg(Color::Yellow))
+                        .data(&peaks),
+                    Dataset::default()
+                        .marker(symbols::Marker::Braille)
+                        .graph_type(GraphType::Line)
+                        .style(Style::default().fg(Color::Green))
+                        .data(&levels),
+                ];
+                let chart = Chart::new(datasets)
+                    .block(Block::default().title(title).borders(Borders::ALL))
+                    .x_axis(Axis::default().bounds([lo, hi]).labels(vec![
+                        hz_label(lo),
+                        hz_label((lo + hi) / 2.),
+                        hz_label(hi),
+                    ]))
+                    .y_axis(
+                        Axis::default()
+                            .bounds([spectrum::FLOOR_DB, 0.])
+                            .labels(vec![Span::from("-96"), Span::from("0")]),
+                    );
+                f.render_widget(chart, schunks[1]);
+
                 let hchunks = Layout::default()
                     .constraints(
                         [
@@ -231,33 +438,14 @@ pub fn ui_loop(
                     .margin(1)
                     .split(vchunks[1]);
 
-                let adsr_attack_for_guage = Gauge::default()
-                    .ratio(adsr_attack_for as f64 / i16::max_value() as f64)
-                    .label("Attach For (d/c)")
-                    .gauge_style(Style::default().fg(Color::White));
-                f.render_widget(adsr_attack_for_guage, hchunks[0]);
-
-                let adsr_attack_to_guage = Gauge::default()
-                    .ratio(adsr_attack_to as f64 / i16::max_value() as f64)
-                    .label("Attach To (f/v)")
-                    .gauge_style(Style::default().fg(Color::White));
-                f.render_widget(adsr_attack_to_guage, hchunks[1]);
-
-                let adsr_decay_for_guage = Gauge::default()
-                    .ratio(adsr_decay_for as f64 / i16::max_value() as f64)
-                    .label("Decay For (g/b)")
-                    .gauge_style(Style::default().fg(Color::White));
-                f.render_widget(adsr_decay_for_guage, hchunks[2]);
-                let adsr_sustain_at_guage = Gauge::default()
-                    .ratio(adsr_sustain_at as f64 / i16::max_value() as f64)
-                    .label("Sustain at (h/n)")
-                    .gauge_style(Style::default().fg(Color::White));
-                f.render_widget(adsr_sustain_at_guage, hchunks[3]);
-                let adsr_release_for_guage = Gauge::default()
-                    .ratio(adsr_release_for as f64 / i16::max_value() as f64)
-                    .label(format!("Release For (j/m) {}", adsr_release_for))
-                    .gauge_style(Style::default().fg(Color::White));
-                f.render_widget(adsr_release_for_guage, hchunks[4]);
+                for (n, (component, port, label, up, down)) in ADSR_SLIDERS.iter().enumerate() {
+                    let value = param(&params, component, port);
+                    let gauge = Gauge::default()
+                        .ratio((value as f64 / i16::MAX as f64).clamp(0., 1.))
+                        .label(format!("{} ({}/{}) {}", label, up, down, value))
+                        .gauge_style(Style::default().fg(Color::White));
+                    f.render_widget(gauge, hchunks[n]);
+                }
 
                 if let Some(i) = scwf_state.state.selected() {
                     if last_scwf_i != i {
@@ -273,76 +461,77 @@ pub fn ui_loop(
         counter += 1;
 
         match events.next().unwrap() {
+            Event::Input(key) if prompt.is_some() => match key {
+                Key::Char('\n') => {
+                    let line = prompt.take().unwrap();
+                    repatch_result = match repatch_cmd(&line) {
+                        Ok(Some(c)) => {
+                            tx.send(c).unwrap();
+                            format!("> {}", line)
+                        }
+                        Ok(None) => String::new(),
+                        Err(e) => e.to_string(),
+                    };
+                }
+                Key::Char(c) => prompt.as_mut().unwrap().push(c),
+                Key::Backspace => {
+                    prompt.as_mut().unwrap().pop();
+                }
+                Key::Esc => prompt = None,
+                _ => {}
+            },
+            Event::Input(Key::Char('\t')) if keys.is_some() => {
+                for note in held.drain(..) {
+                    tx.send(Cmd::NoteOff(note)).unwrap();
+                }
+                keys = None;
+            }
+            Event::Input(Key::Char('\t')) => keys = Some(60),
+            Event::Input(key) if keys.is_some() => match key {
+                Key::Char(c) => {
+                    if let Some(offset) = KEYS.iter().position(|x| *x == c) {
+                        let note = (keys.unwrap() + offset as i16).min(127);
+                        // There's no key up in a terminal, so notes are held
+                        // until their key is pressed again.
+                        if held.contains(&note) {
+                            held.retain(|x| *x != note);
+                            tx.send(Cmd::NoteOff(note)).unwrap();
+                        } else {
+                            held.push(note);
+                            tx.send(Cmd::NoteOn(note)).unwrap();
+                        }
+                    }
+                }
+                Key::Left => keys = keys.map(|x| (x - 12).max(0)),
+                Key::Right => keys = keys.map(|x| (x + 12).min(108)),
+                Key::Esc => {
+                    for note in held.drain(..) {
+                        tx.send(Cmd::NoteOff(note)).unwrap();
+                    }
+                    keys = None;
+                }
+                _ => {}
+            },
+            Event::Input(Key::Char(':')) => prompt = Some(String::new()),
             Event::Input(key) => match key {
                 Key::Char(c) => match c {
+                    ' ' if state == transport::State::Playing => tx.send(Cmd::Pause).unwrap(),
+                    ' ' => tx.send(Cmd::Play).unwrap(),
+                    '.' => tx.send(Cmd::Stop).unwrap(),
+                    ',' => tx.send(Cmd::Locate(0)).unwrap(),
+                    '-' => tx.send(Cmd::Tempo(bpm - 1.)).unwrap(),
+                    '=' => tx.send(Cmd::Tempo(bpm + 1.)).unwrap(),
+                    'o' => spectrum.window = spectrum.window.next(),
+                    'p' => spectrum.clear_peaks(),
+                    'k' => tx.send(Cmd::ClearClips).unwrap(),
+                    '<' => scope.zoom_in(),
+                    '>' => scope.zoom_out(),
+                    '[' => scope.level = scope.level.saturating_sub(i16::MAX / 16),
+                    ']' => scope.level = scope.level.saturating_add(i16::MAX / 16),
+
                     'a' => tx.send(Cmd::Scale(arp1_scale + 1)).unwrap(),
                     'z' => tx.send(Cmd::Scale(arp1_scale - 1)).unwrap(),
 
-                    'd' => tx
-                        .send(Cmd::AdsrAttackFor(
-                            adsr_attack_for.saturating_add(i16::max_value() / 100),
-                        ))
-                        .unwrap(),
-                    'c' => tx
-                        .send(Cmd::AdsrAttackFor(
-                            // Casting to u16 because I want to saturate to 0.
-                            (adsr_attack_for as u16).saturating_sub(i16::max_value() as u16 / 100)
-                                as i16,
-                        ))
-                        .unwrap(),
-
-                    'f' => tx
-                        .send(Cmd::AdsrAttackTo(
-                            adsr_attack_to.saturating_add(i16::max_value() / 100),
-                        ))
-                        .unwrap(),
-                    'v' => tx
-                        .send(Cmd::AdsrAttackTo(
-                            // Casting to u16 because I want to saturate to 0.
-                            (adsr_attack_to as u16).saturating_sub(i16::max_value() as u16 / 100)
-                                as i16,
-                        ))
-                        .unwrap(),
-
-                    'g' => tx
-                        .send(Cmd::AdsrDecayFor(
-                            adsr_decay_for.saturating_add(i16::max_value() / 100),
-                        ))
-                        .unwrap(),
-                    'b' => tx
-                        .send(Cmd::AdsrDecayFor(
-                            // Casting to u16 because I want to saturate to 0.
-                            (adsr_decay_for as u16).saturating_sub(i16::max_value() as u16 / 100)
-                                as i16,
-                        ))
-                        .unwrap(),
-
-                    'h' => tx
-                        .send(Cmd::AdsrSustainAt(
-                            adsr_sustain_at.saturating_add(i16::max_value() / 100),
-                        ))
-                        .unwrap(),
-                    'n' => tx
-                        .send(Cmd::AdsrSustainAt(
-                            // Casting to u16 because I want to saturate to 0.
-                            (adsr_sustain_at as u16).saturating_sub(i16::max_value() as u16 / 100)
-                                as i16,
-                        ))
-                        .unwrap(),
-
-                    'j' => tx
-                        .send(Cmd::AdsrReleaseFor(
-                            adsr_release_for.saturating_add(i16::max_value() / 100),
-                        ))
-                        .unwrap(),
-                    'm' => tx
-                        .send(Cmd::AdsrReleaseFor(
-                            // Casting to u16 because I want to saturate to 0.
-                            (adsr_release_for as u16).saturating_sub(i16::max_value() as u16 / 100)
-                                as i16,
-                        ))
-                        .unwrap(),
-
                     '!' => tx.send(Cmd::Beat(0, 0 == beats[0])).unwrap(),
                     '@' => tx.send(Cmd::Beat(1, 0 == beats[1])).unwrap(),
                     '#' => tx.send(Cmd::Beat(2, 0 == beats[2])).unwrap(),
@@ -377,7 +566,24 @@ pub fn ui_loop(
                     'u' => tx.send(Cmd::Obeat(14, 0 == obeats[14])).unwrap(),
                     'i' => tx.send(Cmd::Obeat(15, 0 == obeats[15])).unwrap(),
 
-                    _ => (),
+                    _ => {
+                        for (component, port, _, up, down) in ADSR_SLIDERS.iter() {
+                            let value = param(&params, component, port);
+                            let value = if c == *up {
+                                value.saturating_add(i16::MAX / 100)
+                            } else if c == *down {
+                                value.saturating_sub(i16::MAX / 100)
+                            } else {
+                                continue;
+                            };
+                            tx.send(Cmd::SetParam {
+                                component: component.to_string(),
+                                port: port.to_string(),
+                                value,
+                            })
+                            .unwrap();
+                        }
+                    }
                 },
                 Key::Esc => break,
                 Key::Left => {}
@@ -395,19 +601,167 @@ pub fn ui_loop(
                 Ok(c) => match c {
                     Cmd::Beat(i, b) => beats[i as usize] = 1 * if b { 1 } else { 0 },
                     Cmd::Obeat(i, b) => obeats[i as usize] = 1 * if b { 1 } else { 0 },
-                    Cmd::FileWaveTable(_) => (),
+                    Cmd::FileWaveTable(_) | Cmd::WaveTable(_) => (),
                     Cmd::Scale(n) => arp1_scale = n,
-                    Cmd::AdsrAttackFor(v) => adsr_attack_for = v,
-                    Cmd::AdsrAttackTo(v) => adsr_attack_to = v,
-                    Cmd::AdsrDecayFor(v) => adsr_decay_for = v,
-                    Cmd::AdsrSustainAt(v) => adsr_sustain_at = v,
-                    Cmd::AdsrReleaseFor(v) => adsr_release_for = v,
+                    Cmd::SetParam { .. } => (),
+                    Cmd::ParamChanged {
+                        component,
+                        port,
+                        value,
+                    } => {
+                        params.insert((component, port), value);
+                    }
+                    Cmd::RepatchFailed(e) => repatch_result = e,
+                    Cmd::Device(d) => device = d,
+                    Cmd::DeviceError(e) => repatch_result = format!("output: {}", e),
+                    Cmd::Cpu(mut c) => {
+                        // The busiest first.
+                        c.sort_by(|a, b| b.1.total_cmp(&a.1));
+                        cpu = c
+                            .iter()
+                            .map(|(name, x)| format!("{} {:.1}%", name, 100. * x))
+                            .collect::<Vec<String>>()
+                            .join(", ");
+                    }
+                    Cmd::Meters(m) => meters = m,
+                    Cmd::ClearClips => (),
+                    Cmd::NoteOn(_) | Cmd::NoteOff(_) => (),
+                    Cmd::Transport {
+                        state: s,
+                        bpm: b,
+                        swing: sw,
+                    } => {
+                        state = s;
+                        bpm = b;
+                        swing = sw;
+                    }
+                    Cmd::Play
+                    | Cmd::Pause
+                    | Cmd::Stop
+                    | Cmd::Locate(_)
+                    | Cmd::Tempo(_)
+                    | Cmd::Swing(_)
+                    | Cmd::LoadGroove(_)
+                    | Cmd::Groove(_) => (),
+                    Cmd::Scope(port) => {
+                        scope.port = port;
+                        scope.clear();
+                        spectrum.clear_peaks();
+                    }
+                    Cmd::AddComponent { .. }
+                    | Cmd::RemoveComponent(_)
+                    | Cmd::Connect(..)
+                    | Cmd::Disconnect(..) => (),
+                    Cmd::DelayedWires(w) => {
+                        delayed_wires = if w.is_empty() {
+                            "none".to_string()
+                        } else {
+                            w.iter()
+                                .map(|(src, dst)| {
+                                    format!("{}.{} -> {}.{}", src.0, src.1, dst.0, dst.1)
+                                })
+                                .collect::<Vec<String>>()
+                                .join(", ")
+                        }
+                    }
                 },
                 Err(TryRecvError::Empty) => break,
                 Err(TryRecvError::Disconnected) => return Ok(()),
             }
         }
-        prev_beat = beat;
     }
     Ok(())
 }
+
+fn transport_cmd(line: &str) -> Result<Option<Cmd>, PatchError> {
+    let words = line.split_whitespace().collect::<Vec<&str>>();
+    let syntax = |msg: String| PatchError::Syntax { line: 0, msg };
+    Ok(Some(match words[..] {
+        ["play"] => Cmd::Play,
+        ["pause"] => Cmd::Pause,
+        ["stop"] => Cmd::Stop,
+        ["locate", step] => Cmd::Locate(
+            step.parse()
+                .map_err(|_| syntax(format!("expected a step to locate to, found `{}`", step)))?,
+        ),
+        ["bpm", tempo] => match tempo.parse::<f64>() {
+            Ok(bpm) if bpm >= 1. && bpm.is_finite() => Cmd::Tempo(bpm),
+            _ => {
+                return Err(syntax(format!(
+                    "expected a tempo of at least 1 bpm, found `{}`",
+                    tempo
+                )))
+            }
+        },
+        ["swing", swing] => match swing.parse::<f64>() {
+            Ok(x) if (transport::MIN_SWING..=transport::MAX_SWING).contains(&x) => Cmd::Swing(x),
+            _ => {
+                return Err(syntax(format!(
+                    "expected a swing from {}% to {}%, found `{}`",
+                    transport::MIN_SWING,
+                    transport::MAX_SWING,
+                    swing
+                )))
+            }
+        },
+        ["groove", "straight"] => Cmd::LoadGroove(None),
+        ["groove", path] => Cmd::LoadGroove(Some(PathBuf::from(path))),
+        ["scope", "master"] => Cmd::Scope(None),
+        ["scope", port] => Cmd::Scope(Some(patch::port_ref(0, port)?)),
+        _ => return Ok(None),
+    }))
+}
+
+fn param(params: &HashMap<PortRef, i16>, component: &str, port: &str) -> i16 {
+    params
+        .get(&(component.to_string(), port.to_string()))
+        .copied()
+        .unwrap_or(0)
+}
+
+// Turns a line typed at the prompt into a command. It takes the same
+// statements as a patch file, along with `rm name` to remove a component
+// and `rm src.port -> dst.port` to remove a wire, and the transport's
+// `play`, `pause`, `stop`, `locate step`, `bpm tempo`, `swing percent` and
+// `groove file` (or `groove straight`), and `scope component.port` (or
+// `scope master`).
+fn repatch_cmd(line: &str) -> Result<Option<Cmd>, PatchError> {
+    if let Some(c) = transport_cmd(line)? {
+        return Ok(Some(c));
+    }
+    if let Some(rest) = line.trim().strip_prefix("rm ") {
+        let rest = rest.trim();
+        if !rest.contains("->") {
+            return Ok(Some(Cmd::RemoveComponent(rest.to_string())));
+        }
+        return match patch::parse_line(0, rest)? {
+            Some(Statement::Wire(src, dst)) => Ok(Some(Cmd::Disconnect(src, dst))),
+            _ => Err(PatchError::Syntax {
+                line: 0,
+                msg: format!("expected `rm src.port -> dst.port`, found `rm {}`", rest),
+            }),
+        };
+    }
+
+    Ok(match patch::parse_line(0, line)? {
+        None => None,
+        Some(Statement::Declare { name, kind, args }) => {
+            Some(Cmd::AddComponent { name, kind, args })
+        }
+        Some(Statement::Set {
+            port: (component, port),
+            value,
+        }) => Some(Cmd::SetParam {
+            component,
+            port,
+            value,
+        }),
+        Some(Statement::Wire(src, dst)) => Some(Cmd::Connect(src, dst)),
+        Some(Statement::Out(_)) => {
+            return Err(PatchError::Syntax {
+                line: 0,
+                msg: "the output can't be changed while playing".to_string(),
+            })
+        }
+    })
+}
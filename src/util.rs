@@ -1,33 +1,138 @@
-use std::ops::{Index, IndexMut};
+use std::fmt;
 
-// Points per second.
-pub const RATE: u32 = 44_100;
+// Points per second, unless the output device or --rate asks for another
+// rate.
+pub const DEFAULT_RATE: u32 = 44_100;
+
+// Beats per minute.
+pub const TEMPO: u64 = 90;
 
 // pub const SEC_PER_TICK: SQ1_31 = SQ32_0::inv_u32(RATE);
 
 pub const WAVE_TABLE_SAMPLES_PER_CYCLE: u32 = 600;
 pub const WAVE_TABLE_SAMPLES_PER_CYCLE_FACTOR: u32 = 256;
 
-// Wave Table Samples per point.
-pub const WAVE_TABLE_SAMPLES_PER_POINT: f64 = (RATE as f64) / (WAVE_TABLE_SAMPLES_PER_CYCLE as f64);
-
-pub fn cv_to_64th_wavetable_increment(cv: i16) -> (f64, u32, f64) {
+pub fn cv_to_64th_wavetable_increment(cv: i16, rate: u32) -> (f64, u32, f64) {
+    // Wave Table Samples per point.
+    let samples_per_point = (rate as f64) / (WAVE_TABLE_SAMPLES_PER_CYCLE as f64);
     let midi_note_index = cv as f64;
     let midi_exp = (midi_note_index - 69.) / 12.;
     let delta = (2f64).powf(midi_exp);
     let f = 440. * delta;
-    let ipc = WAVE_TABLE_SAMPLES_PER_POINT / f;
+    let ipc = samples_per_point / f;
     let ipc_64 = ((WAVE_TABLE_SAMPLES_PER_CYCLE_FACTOR as f64) * ipc) as u32;
     let e = ((ipc_64 as f64) / ((WAVE_TABLE_SAMPLES_PER_CYCLE_FACTOR as f64) * ipc)) - 1.;
 
     (f, ipc_64, e)
 }
 
-pub trait Component:
-    Index<&'static str, Output = i16> + IndexMut<&'static str> + Send + Sync
-{
+// A port name that a component doesn't have.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PortError {
+    pub dir: &'static str,
+    pub port: String,
+    pub valid: Vec<&'static str>,
+}
+
+impl fmt::Display for PortError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "no {} `{}` (expected one of: {})",
+            self.dir,
+            self.port,
+            self.valid.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for PortError {}
+
+// What sort of signal a port carries.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum PortKind {
+    Audio,
+    Cv,
+    // High while held, e.g. while a key is down.
+    Gate,
+    // A short pulse at the start of something, e.g. a note.
+    Trigger,
+    // A note index into the wavetable increment map.
+    Pitch,
+}
+
+// Describes a port, so that UIs and patch files can be checked against
+// it rather than knowing about each component.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PortInfo {
+    pub name: &'static str,
+    pub kind: PortKind,
+    pub unit: &'static str,
+    pub min: i16,
+    pub max: i16,
+    pub default: i16,
+    pub description: &'static str,
+}
+
+impl PortInfo {
+    pub fn contains(&self, value: i16) -> bool {
+        self.min <= value && value <= self.max
+    }
+}
+
+pub fn port_slot(dir: &'static str, ports: &[PortInfo], name: &str) -> Result<usize, PortError> {
+    match ports.iter().position(|x| x.name == name) {
+        Some(slot) => Ok(slot),
+        None => Err(PortError {
+            dir,
+            port: name.to_string(),
+            valid: ports.iter().map(|x| x.name).collect(),
+        }),
+    }
+}
+
+pub trait Component: Send + Sync {
     fn step(&mut self);
-    fn tick(&mut self);
-    fn inputs(&self) -> Vec<&'static str>;
-    fn outputs(&self) -> Vec<&'static str>;
+    // Called on each 16th of the transport's clock, with the velocity the
+    // groove gives the step.
+    fn tick(&mut self, velocity: i16);
+    // Called when the transport moves, with the step the next tick plays.
+    // Only components that count steps need to do anything.
+    fn locate(&mut self, _step: u64) {}
+    fn input_info(&self) -> &'static [PortInfo];
+    fn output_info(&self) -> &'static [PortInfo];
+
+    // Ports are also numbered, in the order given by input_info() and
+    // output_info(), so that wires can be followed without matching strings
+    // every sample.
+    fn input_mut(&mut self, slot: usize) -> &mut i16;
+    fn output(&self, slot: usize) -> i16;
+
+    // Processes `len` samples at once. `inputs` has an entry for each input
+    // slot, with the samples from the wire into it, if any; unwired inputs
+    // keep their current value. `outputs` has a buffer for each output
+    // slot. Components without a faster way to do this just step once per
+    // sample.
+    fn process(&mut self, len: usize, inputs: &[Option<&[i16]>], outputs: &mut [&mut [i16]]) {
+        for n in 0..len {
+            for (slot, input) in inputs.iter().enumerate() {
+                if let Some(input) = input {
+                    *self.input_mut(slot) = input[n];
+                }
+            }
+            self.step();
+            for (slot, output) in outputs.iter_mut().enumerate() {
+                output[n] = self.output(slot);
+            }
+        }
+    }
+
+    // Looking ports up by name is for building patches and the UI; the
+    // audio thread should use the slots.
+    fn input_slot(&self, name: &str) -> Result<usize, PortError> {
+        port_slot("input", self.input_info(), name)
+    }
+    fn output_slot(&self, name: &str) -> Result<usize, PortError> {
+        port_slot("output", self.output_info(), name)
+    }
 }